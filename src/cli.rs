@@ -0,0 +1,161 @@
+/// Twitter to nostr bot.
+///
+/// Every option can be set by the environment variable shown in its help, config values can be
+/// overridden by `TOSTR_<KEY>` variables too (e.g. `TOSTR_SECRET` or `TOSTR_SECRET_FILE`).
+#[derive(clap::Parser, Debug)]
+#[clap(version)]
+pub struct Args {
+    /// Config file, `config.toml` (or legacy `config`) in the current directory by default
+    #[clap(long, env = "TOSTR_CONFIG")]
+    pub config: Option<std::path::PathBuf>,
+
+    /// Directory with the database of followed accounts and other persistent data
+    #[clap(long, env = "TOSTR_DATA_DIR", default_value = "data", global = true)]
+    pub data_dir: std::path::PathBuf,
+
+    /// How to connect to relays and Twitter
+    #[clap(long, value_enum, env = "TOSTR_NETWORK")]
+    pub network: Option<Network>,
+
+    /// Same as --network clearnet, takes precedence over --network and TOSTR_NETWORK
+    #[clap(long, conflicts_with = "tor")]
+    pub clearnet: bool,
+
+    /// Same as --network tor, takes precedence over --network and TOSTR_NETWORK
+    #[clap(long)]
+    pub tor: bool,
+
+    /// Check that every connection goes through the proxy and exit, requires tor network
+    #[clap(long)]
+    pub self_check: bool,
+
+    /// Log filter, e.g. `info` or `tostr=debug`, RUST_LOG is used if not set
+    #[clap(long, env = "TOSTR_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Offline commands, they work with the database directly and don't connect anywhere. Stop the
+/// bot before changing the database, it would overwrite the changes otherwise.
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Manage followed accounts
+    #[clap(subcommand)]
+    Accounts(AccountsCommand),
+    /// Show keys of followed accounts
+    #[clap(subcommand)]
+    Keys(KeysCommand),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AccountsCommand {
+    /// List followed accounts with their state and public key
+    List,
+    /// Follow an account, a new key is generated unless --secret is given
+    Add {
+        username: String,
+        /// Secret key of the account, hex or nsec
+        #[clap(long)]
+        secret: Option<String>,
+    },
+    /// Stop following an account, its key is lost unless exported before
+    Remove { username: String },
+    /// Write all accounts including their secret keys to a file (stdout by default)
+    Export { file: Option<std::path::PathBuf> },
+    /// Add accounts from a file written by export, accounts already followed are skipped
+    Import { file: std::path::PathBuf },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum KeysCommand {
+    /// Show public and secret key of an account
+    Show { username: String },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Network {
+    Clearnet,
+    Tor,
+}
+
+impl Args {
+    /// Returns the network selected by any of the options.
+    pub fn network(&self) -> Option<Network> {
+        if self.clearnet {
+            Some(Network::Clearnet)
+        } else if self.tor {
+            Some(Network::Tor)
+        } else {
+            self.network
+        }
+    }
+
+    pub fn config_path(&self) -> std::path::PathBuf {
+        match &self.config {
+            Some(path) => path.clone(),
+            None => default_config_path(std::path::Path::new("")),
+        }
+    }
+}
+
+/// The `config.toml` shipped with the repository.
+const CONFIG_TEMPLATE: &str = include_str!("../config.toml");
+
+/// Returns `config.toml` in `dir` unless there is a legacy `config` and `config.toml` is missing
+/// or still the untouched template, older installations have only the legacy config.
+fn default_config_path(dir: &std::path::Path) -> std::path::PathBuf {
+    let path = dir.join("config.toml");
+    let legacy = dir.join("config");
+    if !legacy.exists() {
+        return path;
+    }
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) if content != CONFIG_TEMPLATE => path,
+        _ => legacy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn legacy_config_preferred_over_template() {
+        let tmp = utils::TempFile::new("config_dir");
+        let dir = std::path::PathBuf::from(tmp.path());
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(default_config_path(&dir), dir.join("config.toml"));
+
+        std::fs::write(dir.join("config"), "secret = \"abc\"\n").unwrap();
+        assert_eq!(default_config_path(&dir), dir.join("config"));
+
+        std::fs::write(dir.join("config.toml"), CONFIG_TEMPLATE).unwrap();
+        assert_eq!(default_config_path(&dir), dir.join("config"));
+
+        std::fs::write(dir.join("config.toml"), "secret = \"def\"\n").unwrap();
+        assert_eq!(default_config_path(&dir), dir.join("config.toml"));
+    }
+
+    #[test]
+    fn network_flags_override_env() {
+        use clap::Parser;
+
+        std::env::set_var("TOSTR_NETWORK", "clearnet");
+        let args = Args::try_parse_from(["tostr", "--tor"]).unwrap();
+        assert_eq!(args.network(), Some(Network::Tor));
+        let args = Args::try_parse_from(["tostr"]).unwrap();
+        assert_eq!(args.network(), Some(Network::Clearnet));
+
+        std::env::set_var("TOSTR_NETWORK", "tor");
+        let args = Args::try_parse_from(["tostr", "--clearnet"]).unwrap();
+        assert_eq!(args.network(), Some(Network::Clearnet));
+        std::env::remove_var("TOSTR_NETWORK");
+
+        assert!(Args::try_parse_from(["tostr", "--tor", "--clearnet"]).is_err());
+    }
+}
@@ -0,0 +1,643 @@
+use log::{debug, warn};
+
+use crate::media;
+use crate::proxy;
+use crate::utils;
+
+/// Bot configuration, read from a TOML file (see `config.toml`).
+///
+/// Files in the older `key=value` format are still accepted, see [`parse_legacy`].
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub name: String,
+    pub about: String,
+    pub picture_url: String,
+    pub hello_message: String,
+    pub secret: String,
+    pub refresh_interval_secs: u64,
+    pub relays: Vec<String>,
+    pub max_follows: usize,
+    #[serde(default)]
+    pub media_server: Option<media::MediaServer>,
+    #[serde(default)]
+    pub preserve_tweet_time: bool,
+    #[serde(default)]
+    pub link_previews: bool,
+    /// Directory for temporary files, e.g. scraper's output. `scratch` in the data directory by
+    /// default so instances with different data directories don't share it.
+    #[serde(default)]
+    pub scratch_dir: Option<std::path::PathBuf>,
+    /// Single twint run is killed after this.
+    #[serde(default = "default_scraper_timeout_secs")]
+    pub scraper_timeout_secs: u64,
+    /// Bridged accounts get NIP-05 identifier `username@nip05_domain` if set.
+    #[serde(default)]
+    pub nip05_domain: Option<String>,
+    /// Address the built-in `/.well-known/nostr.json` server listens on, it's not started if not set.
+    #[serde(default)]
+    pub nip05_server: Option<std::net::SocketAddr>,
+    #[serde(default)]
+    pub proxy: proxy::ProxyConfig,
+    /// Tweets posted within this many seconds are checked from time to time and their notes are
+    /// deleted (NIP-09) if the tweet was deleted, 0 disables it.
+    #[serde(default)]
+    pub deletion_window_secs: u64,
+    /// Public keys (hex or npub) allowed to use admin commands.
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+fn default_scraper_timeout_secs() -> u64 {
+    300
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("Config")
+            .field("name", &self.name)
+            .field("about", &self.about)
+            .field("picture_url", &self.picture_url)
+            .field("hello_message", &self.hello_message)
+            .field("secret", &"***")
+            .field("refresh_interval_secs", &self.refresh_interval_secs)
+            .field("relays", &self.relays)
+            .field("max_follows", &self.max_follows)
+            .field("media_server", &self.media_server)
+            .field("preserve_tweet_time", &self.preserve_tweet_time)
+            .field("link_previews", &self.link_previews)
+            .field("scratch_dir", &self.scratch_dir)
+            .field("scraper_timeout_secs", &self.scraper_timeout_secs)
+            .field("nip05_domain", &self.nip05_domain)
+            .field("nip05_server", &self.nip05_server)
+            .field("proxy", &self.proxy)
+            .field("deletion_window_secs", &self.deletion_window_secs)
+            .field("admins", &self.admins)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::path::PathBuf, std::io::Error),
+    /// The file isn't valid TOML or a value has wrong type, toml's message names the key.
+    Toml(toml::de::Error),
+    /// Environment variable with a value that can't be used.
+    Env {
+        variable: String,
+        reason: String,
+    },
+    /// Line of the legacy config that couldn't be understood.
+    Legacy {
+        line: usize,
+        reason: String,
+    },
+    Invalid {
+        key: &'static str,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "unable to read {}: {}", path.display(), e),
+            ConfigError::Toml(e) => write!(f, "{}", e),
+            ConfigError::Env { variable, reason } => write!(f, "{}: {}", variable, reason),
+            ConfigError::Legacy { line, reason } => write!(f, "line {}: {}", line, reason),
+            ConfigError::Invalid { key, reason } => write!(f, "invalid `{}`: {}", key, reason),
+        }
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+impl Config {
+    /// Reads the config, applies overrides from environment variables (see
+    /// [`apply_env_overrides`]) and validates the result. Files with `.toml` extension are parsed
+    /// as TOML, anything else in the legacy `key=value` format.
+    pub fn load(path: &std::path::Path) -> Result<Config, ConfigError> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+
+        let mut table = if matches!(path.extension(), Some(ext) if ext == "toml") {
+            toml::from_str::<toml::value::Table>(&content)?
+        } else {
+            warn!(
+                "{} uses the legacy config format, consider moving to TOML (see config.toml)",
+                path.display()
+            );
+            parse_legacy(&content)?
+        };
+        apply_env_overrides(&mut table, std::env::vars())?;
+
+        Config::from_table(table)
+    }
+
+    fn from_table(table: toml::value::Table) -> Result<Config, ConfigError> {
+        let config = toml::Value::Table(table).try_into::<Config>()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Describes every key that differs in `new`, the secret's value is never shown.
+    pub fn diff(&self, new: &Config) -> Vec<String> {
+        let mut changes = vec![];
+        let mut compare = |key: &str, old: String, new: String| {
+            if old != new {
+                changes.push(format!("`{}` changed from {} to {}", key, old, new));
+            }
+        };
+
+        compare(
+            "name",
+            format!("{:?}", self.name),
+            format!("{:?}", new.name),
+        );
+        compare(
+            "about",
+            format!("{:?}", self.about),
+            format!("{:?}", new.about),
+        );
+        compare(
+            "picture_url",
+            format!("{:?}", self.picture_url),
+            format!("{:?}", new.picture_url),
+        );
+        compare(
+            "hello_message",
+            format!("{:?}", self.hello_message),
+            format!("{:?}", new.hello_message),
+        );
+        compare(
+            "refresh_interval_secs",
+            self.refresh_interval_secs.to_string(),
+            new.refresh_interval_secs.to_string(),
+        );
+        compare(
+            "relays",
+            format!("{:?}", self.relays),
+            format!("{:?}", new.relays),
+        );
+        compare(
+            "max_follows",
+            self.max_follows.to_string(),
+            new.max_follows.to_string(),
+        );
+        compare(
+            "media_server",
+            format!("{:?}", self.media_server),
+            format!("{:?}", new.media_server),
+        );
+        compare(
+            "preserve_tweet_time",
+            self.preserve_tweet_time.to_string(),
+            new.preserve_tweet_time.to_string(),
+        );
+        compare(
+            "link_previews",
+            self.link_previews.to_string(),
+            new.link_previews.to_string(),
+        );
+        compare(
+            "scratch_dir",
+            format!("{:?}", self.scratch_dir),
+            format!("{:?}", new.scratch_dir),
+        );
+        compare(
+            "scraper_timeout_secs",
+            self.scraper_timeout_secs.to_string(),
+            new.scraper_timeout_secs.to_string(),
+        );
+        compare(
+            "nip05_domain",
+            format!("{:?}", self.nip05_domain),
+            format!("{:?}", new.nip05_domain),
+        );
+        compare(
+            "nip05_server",
+            format!("{:?}", self.nip05_server),
+            format!("{:?}", new.nip05_server),
+        );
+        compare(
+            "proxy",
+            format!("{:?}", self.proxy),
+            format!("{:?}", new.proxy),
+        );
+        compare(
+            "deletion_window_secs",
+            self.deletion_window_secs.to_string(),
+            new.deletion_window_secs.to_string(),
+        );
+        compare(
+            "admins",
+            format!("{:?}", self.admins),
+            format!("{:?}", new.admins),
+        );
+
+        if self.secret != new.secret {
+            changes.push("`secret` changed".to_string());
+        }
+
+        changes
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, reason: &str| {
+            Err(ConfigError::Invalid {
+                key,
+                reason: reason.to_string(),
+            })
+        };
+
+        for (key, value) in [
+            ("name", &self.name),
+            ("about", &self.about),
+            ("picture_url", &self.picture_url),
+            ("hello_message", &self.hello_message),
+            ("secret", &self.secret),
+        ] {
+            if value.is_empty() {
+                return invalid(key, "must not be empty");
+            }
+        }
+
+        let secp = secp256k1::Secp256k1::new();
+        if secp256k1::KeyPair::from_seckey_str(&secp, &self.secret).is_err() {
+            return invalid("secret", "must be a hex encoded private key");
+        }
+
+        if self.refresh_interval_secs == 0 {
+            return invalid("refresh_interval_secs", "must be greater than 0");
+        }
+        if self.max_follows == 0 {
+            return invalid("max_follows", "must be greater than 0");
+        }
+        if self.scraper_timeout_secs == 0 {
+            return invalid("scraper_timeout_secs", "must be greater than 0");
+        }
+
+        if self.relays.is_empty() {
+            return invalid("relays", "at least one relay is needed");
+        }
+        if let Some(relay) = self
+            .relays
+            .iter()
+            .find(|relay| !relay.starts_with("ws://") && !relay.starts_with("wss://"))
+        {
+            return invalid("relays", &format!(">{}< is not a websocket url", relay));
+        }
+
+        if let Some(media_server) = &self.media_server {
+            if !media_server.url.starts_with("http://") && !media_server.url.starts_with("https://")
+            {
+                return invalid("media_server.url", "must be a http(s) url");
+            }
+        }
+
+        for (key, address) in [
+            ("proxy.address", Some(&self.proxy.address)),
+            ("proxy.relays", self.proxy.relays.as_ref()),
+            ("proxy.sources", self.proxy.sources.as_ref()),
+        ] {
+            if matches!(address, Some(address) if !proxy::is_valid_address(address)) {
+                return invalid(key, "must be host:port, e.g. 127.0.0.1:9050");
+            }
+        }
+
+        if let Some(domain) = &self.nip05_domain {
+            if domain.is_empty() || domain.contains(['/', '@', ':']) {
+                return invalid("nip05_domain", "must be a bare domain, e.g. example.com");
+            }
+        }
+
+        if let Some(admin) = self
+            .admins
+            .iter()
+            .find(|admin| utils::parse_pubkey(admin).is_err())
+        {
+            return invalid("admins", &format!(">{}< is not a public key", admin));
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether hex encoded `pubkey` belongs to one of the admins.
+    pub fn is_admin(&self, pubkey: &str) -> bool {
+        self.admins.iter().any(
+            |admin| matches!(utils::parse_pubkey(admin), Ok(admin) if admin.to_string() == pubkey),
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FieldType {
+    String,
+    Integer,
+    Boolean,
+    /// Comma separated in the environment variables
+    List,
+}
+
+// Every config key with its type, used to convert values from the legacy config and environment
+// variables
+const FIELDS: [(&str, FieldType); 21] = [
+    ("name", FieldType::String),
+    ("about", FieldType::String),
+    ("picture_url", FieldType::String),
+    ("hello_message", FieldType::String),
+    ("secret", FieldType::String),
+    ("refresh_interval_secs", FieldType::Integer),
+    ("relays", FieldType::List),
+    ("max_follows", FieldType::Integer),
+    ("media_server.url", FieldType::String),
+    ("media_server.type", FieldType::String),
+    ("preserve_tweet_time", FieldType::Boolean),
+    ("link_previews", FieldType::Boolean),
+    ("scratch_dir", FieldType::String),
+    ("scraper_timeout_secs", FieldType::Integer),
+    ("nip05_domain", FieldType::String),
+    ("nip05_server", FieldType::String),
+    ("proxy.address", FieldType::String),
+    ("proxy.relays", FieldType::String),
+    ("proxy.sources", FieldType::String),
+    ("deletion_window_secs", FieldType::Integer),
+    ("admins", FieldType::List),
+];
+
+fn typed_value(key: &str, value: &str) -> Result<toml::Value, String> {
+    let field_type = FIELDS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, field_type)| *field_type)
+        .unwrap_or(FieldType::String);
+
+    match field_type {
+        FieldType::String => Ok(toml::Value::String(value.to_string())),
+        FieldType::Integer => value
+            .parse::<i64>()
+            .map(toml::Value::Integer)
+            .map_err(|_| format!("`{}` must be a number", key)),
+        FieldType::Boolean => value
+            .parse::<bool>()
+            .map(toml::Value::Boolean)
+            .map_err(|_| format!("`{}` must be true or false", key)),
+        FieldType::List => Ok(toml::Value::Array(
+            value
+                .split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        )),
+    }
+}
+
+// Inserts `value` under `key`, nested keys are separated by `.`
+fn insert(table: &mut toml::value::Table, key: &str, value: toml::Value) {
+    match key.split_once('.') {
+        Some((parent, child)) => {
+            let parent = table
+                .entry(parent.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+            if let toml::Value::Table(parent) = parent {
+                insert(parent, child, value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Parses the original `key=value` config format (one `addrelay=` line per relay) into the same
+/// structure the TOML config has.
+pub fn parse_legacy(content: &str) -> Result<toml::value::Table, ConfigError> {
+    let mut table = toml::value::Table::new();
+    let mut relays = vec![];
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |reason: String| ConfigError::Legacy {
+            line: index + 1,
+            reason,
+        };
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected key=value, got >{}<", line)))?;
+        let key = key.trim();
+        let mut value = value.trim();
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            value = &value[1..value.len() - 1];
+        }
+
+        let key = match key {
+            "addrelay" => {
+                relays.push(toml::Value::String(value.to_string()));
+                continue;
+            }
+            "media_server" => "media_server.url",
+            "media_server_type" => "media_server.type",
+            key => key,
+        };
+        insert(&mut table, key, typed_value(key, value).map_err(error)?);
+    }
+
+    table.insert("relays".to_string(), toml::Value::Array(relays));
+    Ok(table)
+}
+
+/// Overrides config values by `TOSTR_<KEY>` variables from `vars`, e.g. `TOSTR_SECRET` or
+/// `TOSTR_MEDIA_SERVER_URL`. `TOSTR_RELAYS` is a comma separated list. `TOSTR_SECRET_FILE` reads
+/// the secret from a file so it can be passed as a container secret.
+pub fn apply_env_overrides(
+    table: &mut toml::value::Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<(), ConfigError> {
+    for (variable, value) in vars {
+        let error = |reason: String| ConfigError::Env {
+            variable: variable.clone(),
+            reason,
+        };
+
+        if variable == "TOSTR_SECRET_FILE" {
+            let secret = std::fs::read_to_string(&value)
+                .map_err(|e| error(format!("unable to read {}: {}", value, e)))?;
+            insert(
+                table,
+                "secret",
+                toml::Value::String(secret.trim().to_string()),
+            );
+            continue;
+        }
+
+        let field = FIELDS
+            .iter()
+            .map(|(key, _)| *key)
+            .find(|key| format!("TOSTR_{}", key.replace('.', "_").to_uppercase()) == variable);
+        if let Some(key) = field {
+            debug!("Overriding `{}` by {}", key, variable);
+            insert(table, key, typed_value(key, &value).map_err(error)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+    fn minimal_toml() -> String {
+        format!(
+            r#"
+name = "tostr_bot"
+about = "about"
+picture_url = "https://example.com/pic.jpg"
+hello_message = "hello"
+secret = "{}"
+refresh_interval_secs = 600
+max_follows = 100
+relays = ["wss://relay.example.com"]
+"#,
+            SECRET
+        )
+    }
+
+    fn from_toml(content: &str) -> Result<Config, ConfigError> {
+        Config::from_table(toml::from_str(content)?)
+    }
+
+    #[test]
+    fn parse_toml() {
+        let config = from_toml(&format!(
+            "{}\n[media_server]\nurl = \"https://nip96.example.com\"\ntype = \"nip96\"\n",
+            minimal_toml()
+        ))
+        .unwrap();
+
+        assert_eq!(config.relays, vec!["wss://relay.example.com"]);
+        assert_eq!(config.scraper_timeout_secs, 300);
+        assert_eq!(config.media_server.unwrap().kind, media::ServerKind::Nip96);
+    }
+
+    #[test]
+    fn validation_names_key() {
+        let error = from_toml(
+            &minimal_toml().replace("refresh_interval_secs = 600", "refresh_interval_secs = 0"),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("refresh_interval_secs"), "{}", error);
+
+        let error = from_toml(&format!("{}name_extra = \"x\"\n", minimal_toml()))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("name_extra"), "{}", error);
+
+        let error = from_toml(&minimal_toml().replace("600", "\"soon\""))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("refresh_interval_secs"), "{}", error);
+    }
+
+    #[test]
+    fn parse_legacy_format() {
+        let config = Config::from_table(
+            parse_legacy(&format!(
+                r#"# comment
+name=tostr_bot
+about="Reply with '!help', a=b"
+picture_url=https://example.com/pic.jpg?size=big
+hello_message="hello"
+secret={}
+refresh_interval_secs=600
+max_follows=100
+addrelay=wss://relay.one
+addrelay=wss://relay.two
+media_server=https://blossom.example.com
+link_previews=true
+"#,
+                SECRET
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(config.name, "tostr_bot");
+        assert_eq!(config.about, "Reply with '!help', a=b");
+        assert_eq!(config.picture_url, "https://example.com/pic.jpg?size=big");
+        assert_eq!(config.relays, vec!["wss://relay.one", "wss://relay.two"]);
+        assert_eq!(
+            config.media_server.unwrap().kind,
+            media::ServerKind::Blossom
+        );
+        assert!(config.link_previews);
+
+        let error = Config::from_table(parse_legacy("name_extra=x\n").unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("name_extra"), "{}", error);
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut table = toml::from_str(&minimal_toml().replace(SECRET, "")).unwrap();
+        let vars = [
+            ("TOSTR_SECRET", SECRET),
+            ("TOSTR_RELAYS", "wss://relay.one, wss://relay.two"),
+            ("TOSTR_MAX_FOLLOWS", "5"),
+            ("TOSTR_MEDIA_SERVER_URL", "https://blossom.example.com"),
+            ("HOME", "/root"),
+        ];
+        apply_env_overrides(
+            &mut table,
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+        )
+        .unwrap();
+        let config = Config::from_table(table).unwrap();
+
+        assert_eq!(config.secret, SECRET);
+        assert_eq!(config.relays, vec!["wss://relay.one", "wss://relay.two"]);
+        assert_eq!(config.max_follows, 5);
+        assert_eq!(
+            config.media_server.unwrap().url,
+            "https://blossom.example.com"
+        );
+
+        let mut table = toml::value::Table::new();
+        let error = apply_env_overrides(
+            &mut table,
+            [("TOSTR_MAX_FOLLOWS".to_string(), "many".to_string())],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("TOSTR_MAX_FOLLOWS"), "{}", error);
+    }
+
+    #[test]
+    fn diff_hides_secret() {
+        let old = from_toml(&minimal_toml()).unwrap();
+        let mut new = old.clone();
+        assert!(old.diff(&new).is_empty());
+
+        new.max_follows = 5;
+        new.secret = "0000000000000000000000000000000000000000000000000000000000000002".to_string();
+        let changes = old.diff(&new);
+        assert_eq!(
+            changes,
+            vec!["`max_follows` changed from 100 to 5", "`secret` changed"]
+        );
+    }
+}
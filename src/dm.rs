@@ -0,0 +1,64 @@
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use rand::RngCore;
+
+use crate::utils;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+// x coordinate of the ECDH point is used as the key, see NIP-04
+fn shared_key(secret: &secp256k1::SecretKey, pubkey: &secp256k1::XOnlyPublicKey) -> [u8; 32] {
+    let pubkey = secp256k1::PublicKey::from_x_only_public_key(*pubkey, secp256k1::Parity::Even);
+    let point = secp256k1::ecdh::shared_secret_point(&pubkey, secret);
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&point[..32]);
+    key
+}
+
+/// Returns kind 4 event with `message` encrypted for `recipient`, see NIP-04.
+pub fn get_dm_event(
+    keypair: &secp256k1::KeyPair,
+    recipient: &secp256k1::XOnlyPublicKey,
+    message: &str,
+) -> nostr_bot::EventNonSigned {
+    let key = shared_key(&keypair.secret_key(), recipient);
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let encrypted = Aes256CbcEnc::new(&key.into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(message.as_bytes());
+
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 4,
+        tags: vec![vec!["p".to_string(), recipient.to_string()]],
+        content: format!("{}?iv={}", base64::encode(encrypted), base64::encode(iv)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockDecryptMut;
+
+    #[test]
+    fn dm_can_be_decrypted_by_recipient() {
+        let sender = utils::get_random_keypair();
+        let recipient = utils::get_random_keypair();
+        let recipient_pubkey = recipient.x_only_public_key().0;
+
+        let event = get_dm_event(&sender, &recipient_pubkey, "secret \"message\"");
+        assert_eq!(event.kind, 4);
+        assert_eq!(
+            event.tags,
+            vec![vec!["p".to_string(), recipient_pubkey.to_string()]]
+        );
+
+        let (encrypted, iv) = event.content.split_once("?iv=").unwrap();
+        let key = shared_key(&recipient.secret_key(), &sender.x_only_public_key().0);
+        let iv = base64::decode(iv).unwrap();
+        let decrypted = cbc::Decryptor::<aes::Aes256>::new(&key.into(), iv.as_slice().into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&base64::decode(encrypted).unwrap())
+            .unwrap();
+        assert_eq!(decrypted, b"secret \"message\"");
+    }
+}
@@ -0,0 +1,290 @@
+use log::{debug, warn};
+use std::io::Write;
+
+/// How many checks in a row have to miss a tweet before its note is deleted, a single miss may
+/// be just the scraper not returning everything.
+const MISSES_TO_DELETE: u32 = 2;
+
+/// Note published for a tweet.
+#[derive(Clone, Debug)]
+pub struct Forwarded {
+    pub tweet_id: String,
+    pub event_id: String,
+    /// Key that signed the note, only that one can delete it.
+    pub pubkey: String,
+    /// When the tweet was posted.
+    pub tweet_timestamp: u64,
+    /// How many deletion checks in a row didn't find the tweet.
+    pub misses: u32,
+}
+
+/// Notes published for recent tweets so they can be deleted when the tweet is deleted.
+///
+/// Stored one per line as `username:tweet_id:event_id:pubkey:tweet_timestamp:misses` (`misses` is
+/// missing in files written by older versions), tweets older than the deletion window are dropped
+/// by [`ForwardedTweets::prune`].
+pub struct ForwardedTweets {
+    tweets: std::collections::HashMap<String, Vec<Forwarded>>,
+    file: String,
+}
+
+impl ForwardedTweets {
+    pub fn from_file(path: String) -> ForwardedTweets {
+        let mut forwarded = ForwardedTweets {
+            tweets: std::collections::HashMap::new(),
+            file: path.clone(),
+        };
+
+        if !std::path::Path::new(&path).exists() {
+            debug!("{} doesn't exist, creating a new file", path);
+            std::fs::File::create(&path).expect("Failed to create file");
+        }
+
+        let content = std::fs::read_to_string(path).expect("Failed opening forwarded tweets file");
+        for line in content.lines() {
+            match parse_line(line) {
+                Some((username, tweet)) => {
+                    forwarded.tweets.entry(username).or_default().push(tweet)
+                }
+                None => warn!("Unable to parse forwarded tweet >{}<, skipping", line),
+            }
+        }
+
+        forwarded
+    }
+
+    pub fn insert(&mut self, username: &str, tweet: Forwarded) -> Result<(), String> {
+        let line = format_line(username, &tweet);
+        self.tweets
+            .entry(username.to_string())
+            .or_default()
+            .push(tweet);
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.file)
+            .and_then(|mut file| writeln!(file, "{}", line))
+            .map_err(|e| format!("Unable to write {}: {}", self.file, e))
+    }
+
+    /// Returns notes published for tweets of `username`.
+    pub fn get(&self, username: &str) -> Vec<Forwarded> {
+        self.tweets.get(username).cloned().unwrap_or_default()
+    }
+
+    /// Updates tweets of `username` with result of a deletion check, `existing` are ids of tweets
+    /// the scraper found. Returns tweets that were missed by enough checks in a row to be
+    /// considered deleted.
+    pub fn check(
+        &mut self,
+        username: &str,
+        existing: &std::collections::HashSet<String>,
+    ) -> Result<Vec<Forwarded>, String> {
+        // Losing all the tweets at once is more likely a scraper's problem than deletion
+        if existing.is_empty() {
+            debug!(
+                "No tweets from @{} found, not counting it as a miss",
+                username
+            );
+            return Ok(vec![]);
+        }
+
+        let tweets = match self.tweets.get_mut(username) {
+            Some(tweets) => tweets,
+            None => return Ok(vec![]),
+        };
+        let mut changed = false;
+        for tweet in tweets.iter_mut() {
+            let misses = if existing.contains(&tweet.tweet_id) {
+                0
+            } else {
+                tweet.misses + 1
+            };
+            changed |= misses != tweet.misses;
+            tweet.misses = misses;
+        }
+        let deleted = tweets
+            .iter()
+            .filter(|tweet| tweet.misses >= MISSES_TO_DELETE)
+            .cloned()
+            .collect();
+
+        if changed {
+            self.save()?;
+        }
+        Ok(deleted)
+    }
+
+    /// Forgets notes with ids in `event_ids`.
+    pub fn remove(&mut self, event_ids: &[String]) -> Result<(), String> {
+        for tweets in self.tweets.values_mut() {
+            tweets.retain(|tweet| !event_ids.contains(&tweet.event_id));
+        }
+        self.save()
+    }
+
+    /// Forgets tweets posted before `timestamp`, they are not checked anymore.
+    pub fn prune(&mut self, timestamp: u64) -> Result<(), String> {
+        let count = self.tweets.values().map(Vec::len).sum::<usize>();
+        for tweets in self.tweets.values_mut() {
+            tweets.retain(|tweet| tweet.tweet_timestamp >= timestamp);
+        }
+        self.tweets.retain(|_, tweets| !tweets.is_empty());
+
+        if count == self.tweets.values().map(Vec::len).sum::<usize>() {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    // Rewrites the whole file, see SimpleDatabase::save
+    fn save(&self) -> Result<(), String> {
+        let mut content = String::new();
+        for (username, tweets) in &self.tweets {
+            for tweet in tweets {
+                content.push_str(&format_line(username, tweet));
+                content.push('\n');
+            }
+        }
+
+        let tmp_file = format!("{}.tmp", self.file);
+        std::fs::write(&tmp_file, content)
+            .and_then(|_| std::fs::rename(&tmp_file, &self.file))
+            .map_err(|e| format!("Unable to write {}: {}", self.file, e))
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, Forwarded)> {
+    let mut split = line.split(':');
+    let username = split.next()?.to_string();
+    let tweet = Forwarded {
+        tweet_id: split.next()?.to_string(),
+        event_id: split.next()?.to_string(),
+        pubkey: split.next()?.to_string(),
+        tweet_timestamp: split.next()?.parse().ok()?,
+        misses: match split.next() {
+            Some(misses) => misses.parse().ok()?,
+            None => 0,
+        },
+    };
+    Some((username, tweet))
+}
+
+fn format_line(username: &str, tweet: &Forwarded) -> String {
+    format!(
+        "{}:{}:{}:{}:{}:{}",
+        username, tweet.tweet_id, tweet.event_id, tweet.pubkey, tweet.tweet_timestamp, tweet.misses
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    fn tweet(id: &str, timestamp: u64) -> Forwarded {
+        Forwarded {
+            tweet_id: id.to_string(),
+            event_id: format!("event{}", id),
+            pubkey: "pubkey".to_string(),
+            tweet_timestamp: timestamp,
+            misses: 0,
+        }
+    }
+
+    fn ids(tweets: &[Forwarded]) -> Vec<&str> {
+        let mut ids = tweets
+            .iter()
+            .map(|tweet| tweet.tweet_id.as_str())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn line_roundtrip() {
+        let mut missed = tweet("1", 1660000000);
+        missed.misses = 1;
+        let line = format_line("nasa", &missed);
+        assert_eq!(line, "nasa:1:event1:pubkey:1660000000:1");
+
+        let (username, parsed) = parse_line(&line).unwrap();
+        assert_eq!(username, "nasa");
+        assert_eq!(parsed.tweet_id, "1");
+        assert_eq!(parsed.event_id, "event1");
+        assert_eq!(parsed.pubkey, "pubkey");
+        assert_eq!(parsed.tweet_timestamp, 1660000000);
+        assert_eq!(parsed.misses, 1);
+
+        // Written by older versions
+        assert_eq!(
+            parse_line("nasa:1:event1:pubkey:1660000000")
+                .unwrap()
+                .1
+                .misses,
+            0
+        );
+
+        assert!(parse_line("nasa:1:event1:pubkey").is_none());
+        assert!(parse_line("nasa:1:event1:pubkey:yesterday").is_none());
+        assert!(parse_line("nasa:1:event1:pubkey:1660000000:often").is_none());
+    }
+
+    #[test]
+    fn prune_and_remove() {
+        let file = utils::TempFile::new("forwarded");
+        let mut forwarded = ForwardedTweets::from_file(file.path());
+        forwarded.insert("nasa", tweet("1", 100)).unwrap();
+        forwarded.insert("nasa", tweet("2", 200)).unwrap();
+        forwarded.insert("spacex", tweet("3", 100)).unwrap();
+
+        forwarded.prune(150).unwrap();
+        assert_eq!(ids(&forwarded.get("nasa")), vec!["2"]);
+        assert!(forwarded.get("spacex").is_empty());
+
+        forwarded.insert("nasa", tweet("4", 300)).unwrap();
+        forwarded.remove(&["event2".to_string()]).unwrap();
+        assert_eq!(ids(&forwarded.get("nasa")), vec!["4"]);
+
+        // Survives restart
+        let forwarded = ForwardedTweets::from_file(forwarded.file.clone());
+        assert_eq!(ids(&forwarded.get("nasa")), vec!["4"]);
+        assert!(forwarded.get("spacex").is_empty());
+    }
+
+    #[test]
+    fn deleted_after_consecutive_misses() {
+        let file = utils::TempFile::new("forwarded");
+        let mut forwarded = ForwardedTweets::from_file(file.path());
+        for id in ["1", "2", "3"] {
+            forwarded.insert("nasa", tweet(id, 100)).unwrap();
+        }
+        let found = |ids: &[&str]| {
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<std::collections::HashSet<_>>()
+        };
+
+        // Single miss isn't enough
+        assert!(forwarded.check("nasa", &found(&["1"])).unwrap().is_empty());
+        // Second miss in a row is, found again tweet starts over
+        assert_eq!(
+            ids(&forwarded.check("nasa", &found(&["1", "2"])).unwrap()),
+            vec!["3"]
+        );
+        forwarded.remove(&["event3".to_string()]).unwrap();
+        assert!(forwarded.check("nasa", &found(&["1"])).unwrap().is_empty());
+
+        // Nothing found at all doesn't count as a miss, misses survive restart
+        let mut forwarded = ForwardedTweets::from_file(forwarded.file.clone());
+        assert!(forwarded.check("nasa", &found(&[])).unwrap().is_empty());
+        assert_eq!(
+            ids(&forwarded.check("nasa", &found(&["1"])).unwrap()),
+            vec!["2"]
+        );
+        assert!(forwarded
+            .check("spacex", &found(&["1"]))
+            .unwrap()
+            .is_empty());
+    }
+}
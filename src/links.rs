@@ -0,0 +1,256 @@
+use futures_util::StreamExt;
+use log::{debug, warn};
+use std::io::Write;
+
+use crate::proxy;
+
+const RESOLVE_TIMEOUT_SECS: u64 = 10;
+const MAX_REDIRECTS: usize = 10;
+// Maximum number of links being resolved at the same time (across all workers)
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+// Query parameters used only for tracking, these are removed from the final urls
+const TRACKING_PARAMS: [&str; 14] = [
+    "fbclid", "gclid", "dclid", "gclsrc", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid",
+    "_hsenc", "_hsmi", "mkt_tok", "ref_src", "ref_url",
+];
+
+/// Follows (shortened) links to their final destination.
+///
+/// Resolved t.co links are cached in a file so they don't have to be resolved again after
+/// restart.
+pub struct LinkResolver {
+    client: reqwest::Client,
+    semaphore: tokio::sync::Semaphore,
+    cache: std::sync::Mutex<std::collections::HashMap<String, String>>,
+    file: String,
+}
+
+impl LinkResolver {
+    pub fn new(cache_path: String, proxy: &proxy::ProxyPolicy) -> LinkResolver {
+        let client = proxy
+            .source_client()
+            .timeout(std::time::Duration::from_secs(RESOLVE_TIMEOUT_SECS))
+            .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+            .build()
+            .unwrap();
+
+        let mut cache = std::collections::HashMap::new();
+        if let Ok(content) = std::fs::read_to_string(&cache_path) {
+            for line in content.lines() {
+                match line.split_once(' ') {
+                    Some((short, long)) => {
+                        cache.insert(short.to_string(), long.to_string());
+                    }
+                    None => debug!("unable to parse line: >{}<, skipping", line),
+                }
+            }
+        }
+        debug!("Read {} cached links from {}", cache.len(), cache_path);
+
+        LinkResolver {
+            client,
+            semaphore: tokio::sync::Semaphore::new(MAX_CONCURRENT_REQUESTS),
+            cache: std::sync::Mutex::new(cache),
+            file: cache_path,
+        }
+    }
+
+    /// Client the links are followed with.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Returns `text` with all links replaced by their final destinations.
+    pub async fn expand(&self, text: &str) -> String {
+        let finder = linkify::LinkFinder::new();
+        let links = finder.links(text).collect::<Vec<_>>();
+
+        let mut unique = links
+            .iter()
+            .map(|l| l.as_str().to_string())
+            .collect::<Vec<_>>();
+        unique.sort_unstable();
+        unique.dedup();
+
+        let resolved = futures_util::stream::iter(unique)
+            .map(|link| async move {
+                let resolved = self.resolve(&link).await;
+                (link, resolved)
+            })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .collect::<std::collections::HashMap<_, _>>()
+            .await;
+
+        let mut curr_pos = 0;
+        let mut final_text = String::new();
+
+        for link in &links {
+            final_text.push_str(&text[curr_pos..link.start()]);
+            final_text.push_str(&resolved[link.as_str()]);
+            curr_pos = link.end();
+        }
+        final_text.push_str(&text[curr_pos..]);
+
+        final_text
+    }
+
+    async fn resolve(&self, link: &str) -> String {
+        if let Some(cached) = self.cache.lock().unwrap().get(link) {
+            debug!("Using cached url >{}< for >{}<", cached, link);
+            return cached.clone();
+        }
+
+        let final_url = match self.follow(link).await {
+            Ok(url) => strip_tracking_params(url),
+            Err(e) => {
+                debug!(
+                    "Failed to follow link >{}< ({}), using orignal url",
+                    link, e
+                );
+                // The original url can still carry tracking
+                return match url::Url::parse(link) {
+                    Ok(url) if has_tracking_params(&url) => strip_tracking_params(url),
+                    _ => link.to_string(),
+                };
+            }
+        };
+
+        if is_shortened(link) {
+            self.insert(link, &final_url);
+        }
+
+        final_url
+    }
+
+    // Try HEAD first so the body doesn't have to be downloaded, fallback to GET for servers that
+    // don't support it. The body of GET response is never read.
+    async fn follow(&self, link: &str) -> Result<url::Url, reqwest::Error> {
+        let _permit = self.semaphore.acquire().await.unwrap();
+
+        match self.client.head(link).send().await {
+            Ok(response) if response.status().is_success() => Ok(response.url().clone()),
+            _ => {
+                let response = self.client.get(link).send().await?;
+                Ok(response.url().clone())
+            }
+        }
+    }
+
+    fn insert(&self, short: &str, long: &str) {
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(short.to_string(), long.to_string());
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file);
+        match file {
+            Ok(mut file) => {
+                if let Err(e) = writeln!(file, "{} {}", short, long) {
+                    warn!("Unable to write to link cache {}: {}", self.file, e);
+                }
+            }
+            Err(e) => warn!("Unable to open link cache {}: {}", self.file, e),
+        }
+    }
+}
+
+fn is_shortened(link: &str) -> bool {
+    link.starts_with("https://t.co/") || link.starts_with("http://t.co/")
+}
+
+fn is_tracking(key: &str) -> bool {
+    key.starts_with("utm_") || TRACKING_PARAMS.contains(&key)
+}
+
+fn has_tracking_params(url: &url::Url) -> bool {
+    url.query_pairs().any(|(key, _)| is_tracking(&key))
+}
+
+fn strip_tracking_params(mut url: url::Url) -> String {
+    if has_tracking_params(&url) {
+        let kept = url
+            .query_pairs()
+            .filter(|(key, _)| !is_tracking(key))
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+
+        if kept.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(kept);
+        }
+    }
+
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    fn strip(url: &str) -> String {
+        strip_tracking_params(url::Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn strip_tracking() {
+        assert_eq!(
+            strip("https://example.com/a?utm_source=twitter&id=5&fbclid=x"),
+            "https://example.com/a?id=5"
+        );
+        assert_eq!(
+            strip("https://example.com/a?utm_medium=social"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            strip("https://example.com/a?id=5#top"),
+            "https://example.com/a?id=5#top"
+        );
+    }
+
+    #[tokio::test]
+    async fn cached_links_survive_restart() {
+        let file = utils::TempFile::new("links");
+        let path = file.path();
+        std::fs::write(&path, "https://t.co/abc https://example.com/cached\n").unwrap();
+
+        let resolver = LinkResolver::new(path.clone(), &proxy::ProxyPolicy::default());
+        assert_eq!(
+            resolver.expand("see https://t.co/abc").await,
+            "see https://example.com/cached"
+        );
+
+        resolver.insert("https://t.co/def", "https://example.com/new");
+        let resolver = LinkResolver::new(path.clone(), &proxy::ProxyPolicy::default());
+        assert_eq!(
+            resolver.resolve("https://t.co/def").await,
+            "https://example.com/new"
+        );
+    }
+
+    #[tokio::test]
+    async fn unresolved_links_are_stripped() {
+        let file = utils::TempFile::new("links");
+        let path = file.path();
+        let resolver = LinkResolver::new(path.clone(), &proxy::ProxyPolicy::default());
+
+        // Nothing listens there, the link can't be followed
+        assert_eq!(
+            resolver
+                .resolve("http://127.0.0.1:1/a?utm_source=twitter&id=5")
+                .await,
+            "http://127.0.0.1:1/a?id=5"
+        );
+        assert_eq!(
+            resolver.resolve("http://127.0.0.1:1/a?id=5").await,
+            "http://127.0.0.1:1/a?id=5"
+        );
+        // Only shortened links are cached
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
@@ -1,6 +1,16 @@
-use log::debug;
+use log::{debug, error, info};
 use nostr_bot::FunctorType;
 
+mod cli;
+mod config;
+mod dm;
+mod forwarded;
+mod links;
+mod media;
+mod nip05;
+mod offline;
+mod preview;
+mod proxy;
 mod simpledb;
 mod tostr;
 mod twitter;
@@ -10,18 +20,128 @@ use tostr::State;
 
 #[tokio::main]
 async fn main() {
-    nostr_bot::init_logger();
+    let args = <cli::Args as clap::Parser>::parse();
 
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() != 2 {
-        println!("Usage: {} --clearnet|--tor", args[0]);
-        std::process::exit(1);
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(log_level) = &args.log_level {
+        logger.parse_filters(log_level);
+    }
+    logger.init();
+
+    if let Some(command) = args.command {
+        if let Err(e) = offline::run(command, &args.data_dir) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
     }
 
-    let config_path = std::path::PathBuf::from("config");
-    let config = utils::parse_config(&config_path);
+    let network = match args.network() {
+        Some(network) => network,
+        None => {
+            eprintln!("Select network using --network clearnet|tor (or --clearnet/--tor)");
+            std::process::exit(1);
+        }
+    };
+
+    let config_path = args.config_path();
+    let config = match config::Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid config {}: {}", config_path.display(), e);
+            std::process::exit(1);
+        }
+    };
     debug!("{:?}", config);
 
+    let proxy = proxy::ProxyPolicy::new(network, &config.proxy);
+    debug!("{:?}", proxy);
+
+    let scratch_dir = config
+        .scratch_dir
+        .clone()
+        .unwrap_or_else(|| args.data_dir.join("scratch"));
+    if let Err(e) = twitter::sweep_workfiles(&scratch_dir) {
+        error!(
+            "Unable to prepare scratch directory {}: {}",
+            scratch_dir.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+    std::fs::create_dir_all(&args.data_dir).expect("Failed to create data directory");
+    let data_file = |name: &str| args.data_dir.join(name).to_string_lossy().to_string();
+
+    let link_resolver = std::sync::Arc::new(links::LinkResolver::new(data_file("links"), &proxy));
+    let preview_fetcher = std::sync::Arc::new(preview::PreviewFetcher::new(&proxy));
+    let media_rehoster = std::sync::Arc::new(media::MediaRehoster::new(&proxy));
+
+    // With tor nothing may connect directly, check it every time and not only with --self-check.
+    // Every component is set up the same way as above but with a local canary as the proxy and
+    // then probed whether its requests arrive there.
+    if network == cli::Network::Tor || args.self_check {
+        let canary = match proxy::Canary::bind().await {
+            Ok(canary) => canary,
+            Err(e) => {
+                error!("Unable to start self-check: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let probe_policy = canary.policy(&proxy);
+
+        let scraper_options = twitter::ScraperOptions::new(&config, &probe_policy);
+        let link_resolver = links::LinkResolver::new(data_file("links"), &probe_policy);
+        let preview_fetcher = preview::PreviewFetcher::new(&probe_policy);
+        let media_rehoster = media::MediaRehoster::new(&probe_policy);
+        let routes = [
+            // nostr-bot's connections can't be probed, it gets the relay proxy right from policy
+            ("relays", probe_policy.relays.is_some()),
+            ("scraper", canary.is_in_args(&scraper_options.proxy_args())),
+            (
+                "link follower",
+                canary.is_reached_by(link_resolver.client()).await,
+            ),
+            (
+                "link previews",
+                canary.is_reached_by(preview_fetcher.client()).await,
+            ),
+            (
+                "media re-hosting",
+                canary.is_reached_by(media_rehoster.client()).await,
+            ),
+        ];
+        for (component, proxied) in &routes {
+            info!(
+                "Self-check: {} -> {}",
+                component,
+                if *proxied {
+                    "proxy"
+                } else {
+                    "direct connection"
+                }
+            );
+        }
+
+        if network != cli::Network::Tor {
+            error!("Self-check needs tor network (--tor or --network tor)");
+            std::process::exit(1);
+        }
+
+        let leaks = proxy::find_leaks(&routes);
+        if !leaks.is_empty() {
+            error!(
+                "Refusing to start, these would connect directly: {}",
+                leaks.join(", ")
+            );
+            std::process::exit(1);
+        }
+
+        if args.self_check {
+            println!("Self-check passed, every connection goes through the proxy.");
+            return;
+        }
+    }
+
     let keypair = nostr_bot::keypair_from_secret(&config.secret);
     let sender = nostr_bot::new_sender();
 
@@ -29,9 +149,18 @@ async fn main() {
     let state = nostr_bot::wrap_state(tostr::TostrState {
         config: config.clone(),
         sender: sender.clone(),
+        claims: vec![],
         db: std::sync::Arc::new(std::sync::Mutex::new(simpledb::SimpleDatabase::from_file(
-            "data/users".to_string(),
+            data_file("users"),
         ))),
+        forwarded: std::sync::Arc::new(std::sync::Mutex::new(
+            forwarded::ForwardedTweets::from_file(data_file("forwarded")),
+        )),
+        link_resolver,
+        preview_fetcher,
+        media_rehoster,
+        proxy: proxy.clone(),
+        scratch_dir,
         error_sender: tx.clone(),
         started_timestamp: nostr_bot::unix_timestamp(),
     });
@@ -51,6 +180,29 @@ async fn main() {
         }
     };
 
+    let bot_relay_list = {
+        let state = state.clone();
+        async move {
+            tostr::publish_bot_relay_list(keypair, state).await;
+        }
+    };
+
+    let config_reloader = {
+        let state = state.clone();
+        let config_path = config_path.clone();
+        async move {
+            tostr::config_reloader(config_path, keypair, state).await;
+        }
+    };
+
+    let nip05_server = config.nip05_server.map(|addr| {
+        let state = state.clone();
+        let (bot_pubkey, _parity) = keypair.x_only_public_key();
+        async move {
+            nip05::serve(addr, bot_pubkey, state).await;
+        }
+    });
+
     let relays = config.relays.iter().map(|r| r.as_str()).collect::<Vec<_>>();
 
     let mut bot = nostr_bot::Bot::<State>::new(keypair, relays, state)
@@ -70,10 +222,26 @@ async fn main() {
             nostr_bot::Command::new("!list", nostr_bot::wrap!(tostr::handle_list))
                 .description("Returns list of all accounts that the bot follows."),
         )
+        .command(
+            nostr_bot::Command::new("!status", nostr_bot::wrap!(tostr::handle_status))
+                .description("Shows accounts that are no longer active on Twitter, use '!status username' for a single account."),
+        )
         .command(
             nostr_bot::Command::new("!relays", nostr_bot::wrap_extra!(tostr::handle_relays))
                 .description("Show connected relay."),
         )
+        .command(
+            nostr_bot::Command::new("!claim", nostr_bot::wrap!(tostr::handle_claim))
+                .description("Claim the nostr account bridging your Twitter account, '!claim username'."),
+        )
+        .command(
+            nostr_bot::Command::new("!handover", nostr_bot::wrap!(tostr::handle_handover))
+                .description("Send the key of an account to its owner and stop using it, '!handover username recipient_npub' (admins only)."),
+        )
+        .command(
+            nostr_bot::Command::new("!rotate", nostr_bot::wrap!(tostr::handle_rotate))
+                .description("Replace the key of an account by a new one, '!rotate username' (admins only)."),
+        )
         .command(
             nostr_bot::Command::new("!uptime", nostr_bot::wrap!(tostr::uptime))
                 .description("Prints for how long is the bot running."),
@@ -81,12 +249,16 @@ async fn main() {
         .help()
         .sender(sender)
         .spawn(Box::pin(start_existing))
-        .spawn(Box::pin(error_listener));
+        .spawn(Box::pin(error_listener))
+        .spawn(Box::pin(bot_relay_list))
+        .spawn(Box::pin(config_reloader));
+
+    if let Some(nip05_server) = nip05_server {
+        bot = bot.spawn(Box::pin(nip05_server));
+    }
 
-    match args[1].as_str() {
-        "--clearnet" => {}
-        "--tor" => bot = bot.use_socks5("127.0.0.1:9050"),
-        _ => panic!("Incorrect network settings"),
+    if let Some(proxy_addr) = &proxy.relays {
+        bot = bot.use_socks5(proxy_addr);
     }
 
     bot.run().await;
@@ -0,0 +1,486 @@
+use log::{debug, info, warn};
+use secp256k1::hashes::Hash;
+
+use crate::proxy;
+use crate::utils;
+
+// Don't try to re-host anything bigger than this
+const MAX_MEDIA_SIZE: usize = 20 * 1024 * 1024;
+const MEDIA_TIMEOUT_SECS: u64 = 30;
+// For how long is the upload authorization valid
+const AUTH_EXPIRATION_SECS: u64 = 300;
+// NIP-96 servers may process the media after upload, how often and how many times to ask if it's done
+const PROCESSING_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+const PROCESSING_MAX_POLLS: usize = 30;
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerKind {
+    #[default]
+    Blossom,
+    Nip96,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MediaServer {
+    pub url: String,
+    #[serde(rename = "type", default)]
+    pub kind: ServerKind,
+}
+
+/// Single media attachment of a tweet.
+///
+/// `sha256` and `mime` are known only after the media was re-hosted.
+#[derive(Clone, Debug)]
+pub struct Media {
+    pub url: String,
+    pub sha256: Option<String>,
+    pub mime: Option<String>,
+}
+
+impl Media {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            sha256: None,
+            mime: None,
+        }
+    }
+
+    pub fn is_rehosted(&self) -> bool {
+        self.sha256.is_some()
+    }
+}
+
+pub struct MediaRehoster {
+    client: reqwest::Client,
+}
+
+impl MediaRehoster {
+    pub fn new(proxy: &proxy::ProxyPolicy) -> MediaRehoster {
+        let client = proxy
+            .source_client()
+            .timeout(std::time::Duration::from_secs(MEDIA_TIMEOUT_SECS))
+            .build()
+            .unwrap();
+
+        MediaRehoster { client }
+    }
+
+    /// Client used for both downloads and uploads.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Downloads every media of the tweet and uploads it to the `server`. Media that fail to be
+    /// re-hosted are kept pointing to the original location.
+    pub async fn rehost(
+        &self,
+        media: &mut [Media],
+        server: &MediaServer,
+        keypair: &secp256k1::KeyPair,
+    ) {
+        for item in media.iter_mut() {
+            match rehost_single(&self.client, &item.url, server, keypair).await {
+                Ok(rehosted) => {
+                    info!("Re-hosted {} as {}", item.url, rehosted.url);
+                    *item = rehosted;
+                }
+                Err(e) => warn!("Unable to re-host {}: {}", item.url, e),
+            }
+        }
+    }
+}
+
+async fn rehost_single(
+    client: &reqwest::Client,
+    url: &str,
+    server: &MediaServer,
+    keypair: &secp256k1::KeyPair,
+) -> Result<Media, String> {
+    let (data, mime) = download(client, url).await?;
+    let sha256 = secp256k1::hashes::sha256::Hash::hash(&data).to_string();
+    debug!(
+        "Downloaded {} ({} bytes, sha256 {})",
+        url,
+        data.len(),
+        sha256
+    );
+
+    let uploaded_url = match server.kind {
+        ServerKind::Blossom => {
+            upload_blossom(client, server, data, &mime, &sha256, keypair).await?
+        }
+        ServerKind::Nip96 => upload_nip96(client, server, data, &mime, &sha256, keypair).await?,
+    };
+
+    Ok(Media {
+        url: uploaded_url,
+        sha256: Some(sha256),
+        mime: Some(mime),
+    })
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<(Vec<u8>, String), String> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("download failed: {}", e))?;
+
+    if let Some(length) = response.content_length() {
+        if length as usize > MAX_MEDIA_SIZE {
+            return Err(format!("media too big ({} bytes)", length));
+        }
+    }
+
+    let mime = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    let data = response
+        .bytes()
+        .await
+        .map_err(|e| format!("download failed: {}", e))?;
+
+    if data.len() > MAX_MEDIA_SIZE {
+        return Err(format!("media too big ({} bytes)", data.len()));
+    }
+
+    Ok((data.to_vec(), mime))
+}
+
+/// Upload using BUD-02, see <https://github.com/hzrd149/blossom/blob/master/buds/02.md>.
+async fn upload_blossom(
+    client: &reqwest::Client,
+    server: &MediaServer,
+    data: Vec<u8>,
+    mime: &str,
+    sha256: &str,
+    keypair: &secp256k1::KeyPair,
+) -> Result<String, String> {
+    let auth = nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 24242,
+        tags: vec![
+            vec!["t".to_string(), "upload".to_string()],
+            vec!["x".to_string(), sha256.to_string()],
+            vec![
+                "expiration".to_string(),
+                (utils::unix_timestamp() + AUTH_EXPIRATION_SECS).to_string(),
+            ],
+        ],
+        content: "Upload media".to_string(),
+    }
+    .sign(keypair);
+
+    let response = client
+        .put(format!("{}/upload", server.url.trim_end_matches('/')))
+        .header(reqwest::header::AUTHORIZATION, auth_header(&auth))
+        .header(reqwest::header::CONTENT_TYPE, mime)
+        .body(data)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("upload failed: {}", e))?;
+
+    let descriptor = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| format!("unable to parse blob descriptor: {}", e))?;
+
+    if descriptor["sha256"].as_str() != Some(sha256) {
+        return Err(format!(
+            "server returned different hash {} for {}",
+            descriptor["sha256"], sha256
+        ));
+    }
+
+    descriptor["url"]
+        .as_str()
+        .map(|url| url.to_string())
+        .ok_or_else(|| "blob descriptor is missing url".to_string())
+}
+
+/// Upload using NIP-96, see <https://github.com/nostr-protocol/nips/blob/master/96.md>.
+async fn upload_nip96(
+    client: &reqwest::Client,
+    server: &MediaServer,
+    data: Vec<u8>,
+    mime: &str,
+    sha256: &str,
+    keypair: &secp256k1::KeyPair,
+) -> Result<String, String> {
+    let api_url = nip96_api_url(client, server).await?;
+
+    // NIP-98 authorization
+    let auth = nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 27235,
+        tags: vec![
+            vec!["u".to_string(), api_url.clone()],
+            vec!["method".to_string(), "POST".to_string()],
+            vec!["payload".to_string(), sha256.to_string()],
+        ],
+        content: "".to_string(),
+    }
+    .sign(keypair);
+
+    let part = reqwest::multipart::Part::bytes(data)
+        .file_name("media")
+        .mime_str(mime)
+        .map_err(|e| format!("invalid mime type {}: {}", mime, e))?;
+    let form = reqwest::multipart::Form::new().part("file", part);
+
+    let response = client
+        .post(&api_url)
+        .header(reqwest::header::AUTHORIZATION, auth_header(&auth))
+        .multipart(form)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("upload failed: {}", e))?;
+
+    let accepted = response.status() == reqwest::StatusCode::ACCEPTED;
+    let mut body = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| format!("unable to parse upload response: {}", e))?;
+
+    if accepted || body["status"] == "processing" {
+        body = wait_for_processing(client, &body).await?;
+    }
+
+    if body["status"] != "success" {
+        return Err(format!("upload failed: {}", body["message"]));
+    }
+
+    let tags = body["nip94_event"]["tags"]
+        .as_array()
+        .ok_or_else(|| "upload response is missing nip94_event".to_string())?;
+
+    tags.iter()
+        .find(|tag| tag[0] == "url")
+        .and_then(|tag| tag[1].as_str())
+        .map(|url| url.to_string())
+        .ok_or_else(|| "upload response is missing url".to_string())
+}
+
+// Polls `processing_url` of accepted upload until the server finishes processing the media,
+// returns the final upload response.
+async fn wait_for_processing(
+    client: &reqwest::Client,
+    body: &serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let processing_url = body["processing_url"]
+        .as_str()
+        .ok_or_else(|| "upload is being processed but processing_url is missing".to_string())?;
+
+    for _ in 0..PROCESSING_MAX_POLLS {
+        tokio::time::sleep(PROCESSING_POLL_INTERVAL).await;
+
+        let response = client
+            .get(processing_url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("unable to get processing state: {}", e))?;
+        let created = response.status() == reqwest::StatusCode::CREATED;
+        let body = response
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|e| format!("unable to parse processing state: {}", e))?;
+
+        if created || body["nip94_event"].is_object() {
+            return Ok(body);
+        }
+        if body["status"] == "error" {
+            return Err(format!("processing failed: {}", body["message"]));
+        }
+        debug!(
+            "{} is still being processed ({}%)",
+            processing_url, body["percentage"]
+        );
+    }
+
+    Err(format!(
+        "processing didn't finish in {} s",
+        PROCESSING_POLL_INTERVAL.as_secs() * PROCESSING_MAX_POLLS as u64
+    ))
+}
+
+async fn nip96_api_url(client: &reqwest::Client, server: &MediaServer) -> Result<String, String> {
+    let info = client
+        .get(format!(
+            "{}/.well-known/nostr/nip96.json",
+            server.url.trim_end_matches('/')
+        ))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("unable to get nip96.json: {}", e))?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| format!("unable to parse nip96.json: {}", e))?;
+
+    info["api_url"]
+        .as_str()
+        .map(|url| url.to_string())
+        .ok_or_else(|| "nip96.json is missing api_url".to_string())
+}
+
+fn auth_header(event: &nostr_bot::Event) -> String {
+    format!(
+        "Nostr {}",
+        base64::encode(serde_json::to_string(event).unwrap())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHOTO: &[u8] = b"not really a jpeg";
+
+    // Answers requests of the local stand-in server, gets its base url, method, path, headers and
+    // body, returns status and body
+    type Handler = fn(&str, &hyper::Method, &str, &hyper::HeaderMap, &[u8]) -> (u16, String);
+
+    // Starts local HTTP server answering by `handler`, returns its base url
+    fn stand_in(handler: Handler) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let server_base = base.clone();
+        let make_service = hyper::service::make_service_fn(move |_| {
+            let base = server_base.clone();
+            async move {
+                Ok::<_, hyper::Error>(hyper::service::service_fn(
+                    move |request: hyper::Request<hyper::Body>| {
+                        let base = base.clone();
+                        async move {
+                            let (parts, body) = request.into_parts();
+                            let body = hyper::body::to_bytes(body).await?;
+                            let (status, body) = handler(
+                                &base,
+                                &parts.method,
+                                parts.uri.path(),
+                                &parts.headers,
+                                &body,
+                            );
+                            Ok::<_, hyper::Error>(
+                                hyper::Response::builder()
+                                    .status(status)
+                                    .body(hyper::Body::from(body))
+                                    .unwrap(),
+                            )
+                        }
+                    },
+                ))
+            }
+        });
+        tokio::spawn(
+            hyper::Server::from_tcp(listener)
+                .unwrap()
+                .serve(make_service),
+        );
+
+        base
+    }
+
+    fn is_authorized(headers: &hyper::HeaderMap, kind: u64) -> bool {
+        let event = headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Nostr "))
+            .and_then(|value| base64::decode(value).ok())
+            .and_then(|value| serde_json::from_slice::<serde_json::Value>(&value).ok());
+        matches!(event, Some(event) if event["kind"] == kind)
+    }
+
+    async fn rehost(base: &str, kind: ServerKind) -> Media {
+        let server = MediaServer {
+            url: base.to_string(),
+            kind,
+        };
+        let mut media = vec![Media::new(format!("{}/photo.jpg", base))];
+        MediaRehoster::new(&proxy::ProxyPolicy::default())
+            .rehost(&mut media, &server, &utils::get_random_keypair())
+            .await;
+        media.remove(0)
+    }
+
+    #[tokio::test]
+    async fn rehost_blossom() {
+        let base = stand_in(|base, method, path, headers, body| match (method, path) {
+            (&hyper::Method::GET, "/photo.jpg") => (200, String::from_utf8_lossy(PHOTO).into()),
+            (&hyper::Method::PUT, "/upload") if is_authorized(headers, 24242) => {
+                let sha256 = secp256k1::hashes::sha256::Hash::hash(body).to_string();
+                let descriptor = serde_json::json!({
+                    "sha256": sha256,
+                    "url": format!("{}/{}.jpg", base, sha256),
+                });
+                (200, descriptor.to_string())
+            }
+            _ => (404, "".to_string()),
+        });
+
+        let media = rehost(&base, ServerKind::Blossom).await;
+        let sha256 = secp256k1::hashes::sha256::Hash::hash(PHOTO).to_string();
+        assert_eq!(media.url, format!("{}/{}.jpg", base, sha256));
+        assert_eq!(media.sha256, Some(sha256));
+        assert!(media.is_rehosted());
+    }
+
+    #[tokio::test]
+    async fn rehost_nip96_waits_for_processing() {
+        let base = stand_in(|base, method, path, headers, _body| match (method, path) {
+            (&hyper::Method::GET, "/photo.jpg") => (200, String::from_utf8_lossy(PHOTO).into()),
+            (&hyper::Method::GET, "/.well-known/nostr/nip96.json") => (
+                200,
+                serde_json::json!({ "api_url": format!("{}/api", base) }).to_string(),
+            ),
+            (&hyper::Method::POST, "/api") if is_authorized(headers, 27235) => (
+                202,
+                serde_json::json!({
+                    "status": "processing",
+                    "message": "Processing",
+                    "processing_url": format!("{}/processing", base),
+                })
+                .to_string(),
+            ),
+            (&hyper::Method::GET, "/processing") => (
+                201,
+                serde_json::json!({
+                    "status": "success",
+                    "nip94_event": { "tags": [["url", format!("{}/done.jpg", base)]] },
+                })
+                .to_string(),
+            ),
+            _ => (404, "".to_string()),
+        });
+
+        let media = rehost(&base, ServerKind::Nip96).await;
+        assert_eq!(media.url, format!("{}/done.jpg", base));
+        assert!(media.is_rehosted());
+    }
+
+    #[tokio::test]
+    async fn failed_upload_keeps_original() {
+        let base = stand_in(
+            |_base, method, path, _headers, _body| match (method, path) {
+                (&hyper::Method::GET, "/photo.jpg") => (200, String::from_utf8_lossy(PHOTO).into()),
+                _ => (500, "".to_string()),
+            },
+        );
+
+        let media = rehost(&base, ServerKind::Blossom).await;
+        assert_eq!(media.url, format!("{}/photo.jpg", base));
+        assert!(!media.is_rehosted());
+    }
+}
@@ -0,0 +1,192 @@
+use log::{debug, info, warn};
+
+use crate::tostr::State;
+
+const NOSTR_JSON_PATH: &str = "/.well-known/nostr.json";
+
+/// Serves `/.well-known/nostr.json` (NIP-05) for every bridged account and for the bot itself
+/// (as `_`) so `username@nip05_domain` identifiers can be verified. The domain has to be proxied
+/// to `addr`.
+pub async fn serve(
+    addr: std::net::SocketAddr,
+    bot_pubkey: secp256k1::XOnlyPublicKey,
+    state: State,
+) {
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |request| {
+                handle(request, bot_pubkey, state.clone())
+            }))
+        }
+    });
+
+    let server = match hyper::Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            warn!("Unable to start NIP-05 server on {}: {}", addr, e);
+            return;
+        }
+    };
+
+    info!("Serving NIP-05 identifiers on {}", addr);
+    if let Err(e) = server.await {
+        warn!("NIP-05 server failed: {}", e);
+    }
+}
+
+async fn handle(
+    request: hyper::Request<hyper::Body>,
+    bot_pubkey: secp256k1::XOnlyPublicKey,
+    state: State,
+) -> Result<hyper::Response<hyper::Body>, std::convert::Infallible> {
+    debug!("NIP-05 request {} {}", request.method(), request.uri());
+
+    let (relays, follows) = {
+        let state = state.lock().await;
+        let follows = state.db.lock().unwrap().get_follows();
+        (state.config.relays.clone(), follows)
+    };
+
+    Ok(respond(&request, bot_pubkey, &follows, &relays))
+}
+
+// Answers the request from bridged accounts `follows`, `relays` are returned as relay hints for
+// every identifier.
+fn respond(
+    request: &hyper::Request<hyper::Body>,
+    bot_pubkey: secp256k1::XOnlyPublicKey,
+    follows: &std::collections::HashMap<String, secp256k1::KeyPair>,
+    relays: &[String],
+) -> hyper::Response<hyper::Body> {
+    if request.method() != hyper::Method::GET || request.uri().path() != NOSTR_JSON_PATH {
+        let mut response = hyper::Response::new(hyper::Body::from("Not found"));
+        *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let name = request.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "name")
+            .map(|(_, value)| value.to_lowercase())
+    });
+
+    let mut pubkeys = follows
+        .iter()
+        .map(|(username, keypair)| {
+            let (pubkey, _parity) = keypair.x_only_public_key();
+            (username.to_lowercase(), pubkey)
+        })
+        .collect::<std::collections::BTreeMap<_, _>>();
+    pubkeys.insert("_".to_string(), bot_pubkey);
+
+    // Without a name only the bot itself is returned instead of every account
+    let name = name.unwrap_or_else(|| "_".to_string());
+    pubkeys.retain(|username, _| *username == name);
+
+    let body = serde_json::json!({
+        "names": pubkeys
+            .iter()
+            .map(|(username, pubkey)| (username.clone(), pubkey.to_string()))
+            .collect::<std::collections::BTreeMap<_, _>>(),
+        "relays": pubkeys
+            .values()
+            .map(|pubkey| (pubkey.to_string(), relays.to_vec()))
+            .collect::<std::collections::BTreeMap<_, _>>(),
+    });
+
+    hyper::Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        // Required by NIP-05 so web clients can verify the identifiers
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(hyper::Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    struct Setup {
+        bot: secp256k1::XOnlyPublicKey,
+        nasa: secp256k1::XOnlyPublicKey,
+        follows: std::collections::HashMap<String, secp256k1::KeyPair>,
+        relays: Vec<String>,
+    }
+
+    fn setup() -> Setup {
+        let nasa = utils::get_random_keypair();
+        let spacex = utils::get_random_keypair();
+        Setup {
+            bot: utils::get_random_keypair().x_only_public_key().0,
+            nasa: nasa.x_only_public_key().0,
+            follows: std::collections::HashMap::from([
+                ("nasa".to_string(), nasa),
+                ("spacex".to_string(), spacex),
+            ]),
+            relays: vec!["wss://relay.example.com".to_string()],
+        }
+    }
+
+    async fn get(setup: &Setup, uri: &str) -> (hyper::Response<hyper::Body>, serde_json::Value) {
+        let request = hyper::Request::get(uri).body(hyper::Body::empty()).unwrap();
+        let mut response = respond(&request, setup.bot, &setup.follows, &setup.relays);
+        let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+        let json = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+        (response, json)
+    }
+
+    #[tokio::test]
+    async fn name_lookup() {
+        let setup = setup();
+        let (response, json) = get(&setup, "/.well-known/nostr.json?name=NASA").await;
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            response.headers()[hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "*"
+        );
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/json"
+        );
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "names": {"nasa": setup.nasa.to_string()},
+                "relays": {setup.nasa.to_string(): ["wss://relay.example.com"]},
+            })
+        );
+
+        let (_, json) = get(&setup, "/.well-known/nostr.json?name=esa").await;
+        assert_eq!(json, serde_json::json!({"names": {}, "relays": {}}));
+    }
+
+    #[tokio::test]
+    async fn bot_by_default() {
+        let setup = setup();
+        let expected = serde_json::json!({
+            "names": {"_": setup.bot.to_string()},
+            "relays": {setup.bot.to_string(): ["wss://relay.example.com"]},
+        });
+
+        let (_, json) = get(&setup, "/.well-known/nostr.json").await;
+        assert_eq!(json, expected);
+        let (_, json) = get(&setup, "/.well-known/nostr.json?name=_").await;
+        assert_eq!(json, expected);
+    }
+
+    #[tokio::test]
+    async fn other_paths() {
+        let setup = setup();
+        let (response, _) = get(&setup, "/nostr.json?name=nasa").await;
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        let request = hyper::Request::post(NOSTR_JSON_PATH)
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = respond(&request, setup.bot, &setup.follows, &setup.relays);
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::cli::{AccountsCommand, Command, KeysCommand};
+use crate::simpledb;
+use crate::twitter;
+use crate::utils;
+
+/// Runs offline `command` on the database in `data_dir`.
+pub fn run(command: Command, data_dir: &std::path::Path) -> Result<(), String> {
+    std::fs::create_dir_all(data_dir)
+        .map_err(|e| format!("Unable to create {}: {}", data_dir.display(), e))?;
+    let mut db =
+        simpledb::SimpleDatabase::from_file(data_dir.join("users").to_string_lossy().to_string());
+
+    match command {
+        Command::Accounts(AccountsCommand::List) => {
+            for (username, account) in db.get_accounts() {
+                let secret = utils::parse_secret(&account.secret)?;
+                let keypair = secret.keypair(&secp256k1::Secp256k1::new());
+                let state = match account.handed_over {
+                    Some(_) => "handed over".to_string(),
+                    None => account.state.description(),
+                };
+                println!(
+                    "{}\t{}\t{}",
+                    username,
+                    state,
+                    utils::npub(&keypair.x_only_public_key().0)
+                );
+            }
+        }
+        Command::Accounts(AccountsCommand::Add { username, secret }) => {
+            let username = username.to_ascii_lowercase().replace('@', "");
+            if !twitter::is_valid_username(&username) {
+                return Err(format!("{} is not a valid Twitter username", username));
+            }
+            let keypair = match secret {
+                Some(secret) => utils::parse_secret(&secret)?.keypair(&secp256k1::Secp256k1::new()),
+                None => utils::get_random_keypair(),
+            };
+            db.insert(username.clone(), keypair.display_secret().to_string())
+                .map_err(|e| format!("Unable to add {}: {}", username, e))?;
+            println!(
+                "Added {} as {}",
+                username,
+                utils::npub(&keypair.x_only_public_key().0)
+            );
+        }
+        Command::Accounts(AccountsCommand::Remove { username }) => {
+            let username = username.to_ascii_lowercase().replace('@', "");
+            db.remove(&username)?;
+            println!("Removed {}", username);
+        }
+        Command::Accounts(AccountsCommand::Export { file }) => match file {
+            Some(file) => {
+                write_private(&file, &db.export())
+                    .map_err(|e| format!("Unable to write {}: {}", file.display(), e))?;
+                println!(
+                    "Exported {} accounts to {}",
+                    db.follows_count(),
+                    file.display()
+                );
+            }
+            None => print!("{}", db.export()),
+        },
+        Command::Accounts(AccountsCommand::Import { file }) => {
+            let content = std::fs::read_to_string(&file)
+                .map_err(|e| format!("Unable to read {}: {}", file.display(), e))?;
+            let imported = db.import(&content)?;
+            println!("Imported {} accounts", imported.len());
+        }
+        Command::Keys(KeysCommand::Show { username }) => {
+            let username = username.to_ascii_lowercase().replace('@', "");
+            let account = db
+                .get_account(&username)
+                .ok_or(format!("{} is not in the database", username))?;
+            let secret = utils::parse_secret(&account.secret)?;
+            let (pubkey, _parity) = secret
+                .keypair(&secp256k1::Secp256k1::new())
+                .x_only_public_key();
+            println!("npub: {}", utils::npub(&pubkey));
+            println!("pubkey: {}", pubkey);
+            println!("nsec: {}", utils::nsec(&secret));
+        }
+    }
+
+    Ok(())
+}
+
+// Exported file contains secret keys so only the owner can read it
+fn write_private(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content.as_bytes())
+}
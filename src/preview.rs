@@ -0,0 +1,231 @@
+use log::debug;
+
+use crate::proxy;
+
+const PREVIEW_TIMEOUT_SECS: u64 = 10;
+// Only the beginning of the page is read, OpenGraph tags are expected in <head>
+const MAX_PREVIEW_SIZE: usize = 512 * 1024;
+const MAX_TITLE_CHARS: usize = 100;
+const MAX_DESCRIPTION_CHARS: usize = 200;
+
+/// OpenGraph info about a linked article.
+#[derive(Clone, Debug)]
+pub struct Preview {
+    pub url: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub image: Option<String>,
+}
+
+pub struct PreviewFetcher {
+    client: reqwest::Client,
+}
+
+impl PreviewFetcher {
+    pub fn new(proxy: &proxy::ProxyPolicy) -> PreviewFetcher {
+        let client = proxy
+            .source_client()
+            .timeout(std::time::Duration::from_secs(PREVIEW_TIMEOUT_SECS))
+            .build()
+            .unwrap();
+
+        PreviewFetcher { client }
+    }
+
+    /// Client the pages are fetched with, `--self-check` probes it.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Returns preview for the `url` or None if it doesn't have any OpenGraph title.
+    pub async fn fetch(&self, url: &str) -> Option<Preview> {
+        match self.read_head(url).await {
+            Ok(html) => {
+                let preview = parse_opengraph(url, &html);
+                debug!("Preview for {}: {:?}", url, preview);
+                preview
+            }
+            Err(e) => {
+                debug!("Unable to get preview for {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    async fn read_head(&self, url: &str) -> Result<String, String> {
+        let mut response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+
+        let is_html = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with("text/html"))
+            .unwrap_or(false);
+        if !is_html {
+            return Err("not a html page".to_string());
+        }
+
+        let mut body = vec![];
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            body.extend_from_slice(&chunk);
+            if body.len() >= MAX_PREVIEW_SIZE || contains(&body, b"</head>") {
+                break;
+            }
+        }
+        body.truncate(MAX_PREVIEW_SIZE);
+
+        Ok(String::from_utf8_lossy(&body).to_string())
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+fn parse_opengraph(url: &str, html: &str) -> Option<Preview> {
+    let mut title = None;
+    let mut description = None;
+    let mut image = None;
+
+    for tag in html.split("<meta").skip(1) {
+        let tag = match tag.find('>') {
+            Some(end) => &tag[..end],
+            None => continue,
+        };
+
+        let property = attribute(tag, "property").or_else(|| attribute(tag, "name"));
+        let content = match attribute(tag, "content") {
+            Some(content) if !content.is_empty() => content,
+            _ => continue,
+        };
+
+        match property.as_deref() {
+            Some("og:title") => title = Some(content),
+            Some("og:description") => description = Some(content),
+            Some("og:image") => image = Some(content),
+            _ => {}
+        }
+    }
+
+    title.map(|title| Preview {
+        url: url.to_string(),
+        title: truncate(title, MAX_TITLE_CHARS),
+        description: description.map(|d| truncate(d, MAX_DESCRIPTION_CHARS)),
+        image,
+    })
+}
+
+fn truncate(text: String, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        let mut truncated = text.chars().take(max_chars).collect::<String>();
+        truncated.push('…');
+        truncated
+    } else {
+        text
+    }
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(pos) = rest.find(name) {
+        let after = rest[pos + name.len()..].trim_start();
+        let preceded_by_space = rest[..pos].ends_with(char::is_whitespace);
+        rest = &rest[pos + name.len()..];
+
+        if !preceded_by_space || !after.starts_with('=') {
+            continue;
+        }
+
+        let value = after[1..].trim_start();
+        let quote = value.chars().next()?;
+        let value = if quote == '"' || quote == '\'' {
+            let value = &value[1..];
+            &value[..value.find(quote)?]
+        } else {
+            // `/` belongs to the value (e.g. url), self-closing tag needs a space before it
+            value.split(char::is_whitespace).next()?
+        };
+
+        return Some(decode_entities(value.trim()));
+    }
+
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_article() {
+        let html = r#"<html><head>
+            <meta charset="utf-8">
+            <meta property="og:title" content="Artemis &amp; the Moon" />
+            <meta name="og:description" content='It&#39;s "going" well'>
+            <meta property="og:image" content=https://example.com/moon.jpg>
+            <meta property="og:url" content="https://example.com/ignored">
+            </head><body>"#;
+
+        let preview = parse_opengraph("https://example.com/a", html).unwrap();
+        assert_eq!(preview.url, "https://example.com/a");
+        assert_eq!(preview.title, "Artemis & the Moon");
+        assert_eq!(preview.description.as_deref(), Some("It's \"going\" well"));
+        assert_eq!(
+            preview.image.as_deref(),
+            Some("https://example.com/moon.jpg")
+        );
+    }
+
+    #[test]
+    fn parse_without_title() {
+        let html = r#"<meta property="og:description" content="No title">
+            <meta property="og:title" content="">"#;
+        assert!(parse_opengraph("https://example.com", html).is_none());
+    }
+
+    #[test]
+    fn parse_truncates() {
+        let html = format!(
+            r#"<meta property="og:title" content="{}"><meta property="og:description" content="{}">"#,
+            "t".repeat(150),
+            "ž".repeat(250)
+        );
+        let preview = parse_opengraph("https://example.com", &html).unwrap();
+        assert_eq!(preview.title, format!("{}…", "t".repeat(MAX_TITLE_CHARS)));
+        assert_eq!(
+            preview.description,
+            Some(format!("{}…", "ž".repeat(MAX_DESCRIPTION_CHARS)))
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let tag = r#" data-property="x" property = "og:title" content='a "b"' "#;
+        assert_eq!(attribute(tag, "property").as_deref(), Some("og:title"));
+        assert_eq!(attribute(tag, "content").as_deref(), Some("a \"b\""));
+        assert_eq!(
+            attribute(" content=plain />", "content").as_deref(),
+            Some("plain")
+        );
+        assert_eq!(attribute(" name", "name"), None);
+        assert_eq!(attribute(" content=\"unterminated", "content"), None);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::cli;
+
+/// `[proxy]` section of the config.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// socks5 proxy used for everything when running with `--tor`.
+    #[serde(default = "default_address")]
+    pub address: String,
+    /// Overrides `address` for connections to relays, used even with `--clearnet`.
+    #[serde(default)]
+    pub relays: Option<String>,
+    /// Overrides `address` for connections to Twitter and linked sites (scraper, link expansion,
+    /// previews, media), used even with `--clearnet`.
+    #[serde(default)]
+    pub sources: Option<String>,
+}
+
+fn default_address() -> String {
+    "127.0.0.1:9050".to_string()
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            address: default_address(),
+            relays: None,
+            sources: None,
+        }
+    }
+}
+
+/// Checks `address` looks like `host:port`.
+pub fn is_valid_address(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+/// Decides which socks5 proxy (if any) every outbound connection goes through. All components
+/// that connect anywhere get their proxy from here.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProxyPolicy {
+    pub relays: Option<String>,
+    pub sources: Option<String>,
+}
+
+impl ProxyPolicy {
+    pub fn new(network: cli::Network, config: &ProxyConfig) -> ProxyPolicy {
+        match network {
+            cli::Network::Tor => ProxyPolicy {
+                relays: Some(
+                    config
+                        .relays
+                        .clone()
+                        .unwrap_or_else(|| config.address.clone()),
+                ),
+                sources: Some(
+                    config
+                        .sources
+                        .clone()
+                        .unwrap_or_else(|| config.address.clone()),
+                ),
+            },
+            cli::Network::Clearnet => ProxyPolicy {
+                relays: config.relays.clone(),
+                sources: config.sources.clone(),
+            },
+        }
+    }
+
+    /// Returns builder of HTTP client for connections to sources, DNS is resolved by the proxy
+    /// too.
+    pub fn source_client(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder();
+        match &self.sources {
+            Some(proxy) => builder.proxy(
+                reqwest::Proxy::all(format!("socks5h://{}", proxy))
+                    .expect("Invalid socks5 proxy address"),
+            ),
+            None => builder,
+        }
+    }
+}
+
+/// How long the self-check waits for a component to reach the canary.
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Never resolved by anyone but the proxy (RFC 6761), a direct connection fails right away.
+const PROBE_URL: &str = "http://self-check.invalid/";
+
+/// Local listener standing in for the socks5 proxy during the self-check. Components are set up
+/// with it as their proxy and then probed whether their requests really arrive there.
+pub struct Canary {
+    listener: tokio::net::TcpListener,
+}
+
+impl Canary {
+    pub async fn bind() -> std::io::Result<Canary> {
+        Ok(Canary {
+            listener: tokio::net::TcpListener::bind("127.0.0.1:0").await?,
+        })
+    }
+
+    /// Returns `policy` with every proxy replaced by the canary, components that would connect
+    /// directly still do.
+    pub fn policy(&self, policy: &ProxyPolicy) -> ProxyPolicy {
+        let address = self.listener.local_addr().unwrap().to_string();
+        ProxyPolicy {
+            relays: policy.relays.as_ref().map(|_| address.clone()),
+            sources: policy.sources.as_ref().map(|_| address.clone()),
+        }
+    }
+
+    /// Sends a request with `client` and returns whether it greeted the canary as socks5 client.
+    pub async fn is_reached_by(&self, client: &reqwest::Client) -> bool {
+        let greeting = async {
+            let (mut stream, _) = self.listener.accept().await?;
+            let mut version = [0u8];
+            tokio::io::AsyncReadExt::read_exact(&mut stream, &mut version).await?;
+            // Dropping the stream makes the request fail, it doesn't have to go anywhere
+            Ok::<_, std::io::Error>(version[0] == 5)
+        };
+
+        // Request going through the proxy can't finish before the canary closes the connection,
+        // if it does it went elsewhere
+        tokio::select! {
+            greeting = greeting => matches!(greeting, Ok(true)),
+            _ = client.get(PROBE_URL).send() => false,
+            _ = tokio::time::sleep(PROBE_TIMEOUT) => false,
+        }
+    }
+
+    /// Returns whether twint `args` point it to the canary.
+    pub fn is_in_args(&self, args: &[&str]) -> bool {
+        let address = self.listener.local_addr().unwrap();
+        let (host, port) = (address.ip().to_string(), address.port().to_string());
+        args.windows(2).any(|arg| arg == ["--proxy-host", &host])
+            && args.windows(2).any(|arg| arg == ["--proxy-port", &port])
+    }
+}
+
+/// Returns components that would connect directly. `routes` holds whether the requests of every
+/// component got through the proxy.
+pub fn find_leaks<'a>(routes: &[(&'a str, bool)]) -> Vec<&'a str> {
+    routes
+        .iter()
+        .filter(|(_, proxied)| !proxied)
+        .map(|(component, _)| *component)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn canary_catches_direct_connections() {
+        let canary = Canary::bind().await.unwrap();
+        let tor = ProxyPolicy {
+            relays: Some("127.0.0.1:9050".to_string()),
+            sources: Some("127.0.0.1:9050".to_string()),
+        };
+
+        let proxied = canary.policy(&tor).source_client().build().unwrap();
+        assert!(canary.is_reached_by(&proxied).await);
+
+        let direct = canary
+            .policy(&ProxyPolicy::default())
+            .source_client()
+            .build()
+            .unwrap();
+        assert!(!canary.is_reached_by(&direct).await);
+    }
+
+    #[tokio::test]
+    async fn canary_in_args() {
+        let canary = Canary::bind().await.unwrap();
+        let address = canary.listener.local_addr().unwrap().to_string();
+        let (host, port) = address.rsplit_once(':').unwrap();
+
+        assert!(canary.is_in_args(&[
+            "--proxy-type",
+            "socks5",
+            "--proxy-host",
+            host,
+            "--proxy-port",
+            port
+        ]));
+        assert!(!canary.is_in_args(&["--proxy-host", host, "--proxy-port", "9050"]));
+        assert!(!canary.is_in_args(&[]));
+    }
+}
@@ -1,10 +1,110 @@
 use log::{debug, warn};
 use std::io::Write;
 
+use crate::twitter::AccountState;
+use crate::utils;
+
 pub type Database = std::sync::Arc<std::sync::Mutex<SimpleDatabase>>;
 
+/// Everything tostr knows about a followed account.
+///
+/// Stored as one line `username:secret[:key=value]...` so databases created by older versions
+/// (just `username:secret`) can still be read.
+#[derive(Clone, Debug)]
+pub struct Account {
+    pub secret: String,
+    pub state: AccountState,
+    /// Twitter's id of the account, it doesn't change when the account is renamed.
+    pub user_id: Option<String>,
+    /// Hash of the last published profile (kind 0) so it isn't sent again when nothing changed.
+    pub metadata_hash: Option<String>,
+    /// Hash of the relays in the last published relay list (kind 10002).
+    pub relay_list_hash: Option<String>,
+    /// Public key of the owner the secret was handed over to, tostr doesn't post with it anymore.
+    pub handed_over: Option<String>,
+    /// Secrets used before the key was rotated, kept only for deleting notes signed by them.
+    pub old_secrets: Vec<String>,
+}
+
+impl Account {
+    fn new(secret: String) -> Self {
+        Self {
+            secret,
+            state: AccountState::Active,
+            user_id: None,
+            metadata_hash: None,
+            relay_list_hash: None,
+            handed_over: None,
+            old_secrets: vec![],
+        }
+    }
+
+    /// Returns the current keypair followed by the ones used before rotations. Fails if any of
+    /// the secrets is invalid.
+    pub fn keypairs(&self) -> Result<Vec<secp256k1::KeyPair>, String> {
+        let secp = secp256k1::Secp256k1::new();
+        std::iter::once(&self.secret)
+            .chain(&self.old_secrets)
+            .map(|secret| utils::parse_secret(secret).map(|secret| secret.keypair(&secp)))
+            .collect()
+    }
+
+    fn parse(line: &str) -> Option<(String, Account)> {
+        let mut split = line.split(':');
+        let username = split.next()?;
+        let secret = split.next()?;
+        if username.is_empty() || secret.is_empty() {
+            return None;
+        }
+
+        let mut account = Account::new(secret.to_string());
+
+        for field in split {
+            match field.split_once('=') {
+                Some(("state", value)) => match value.parse::<AccountState>() {
+                    Ok(state) => account.state = state,
+                    Err(e) => warn!("{} for {}, using default", e, username),
+                },
+                Some(("user_id", value)) => account.user_id = Some(value.to_string()),
+                Some(("metadata_hash", value)) => account.metadata_hash = Some(value.to_string()),
+                Some(("relay_list_hash", value)) => {
+                    account.relay_list_hash = Some(value.to_string())
+                }
+                Some(("handed_over", value)) => account.handed_over = Some(value.to_string()),
+                Some(("old_secret", value)) => account.old_secrets.push(value.to_string()),
+                _ => warn!("Unknown field >{}< for {}, ignoring", field, username),
+            }
+        }
+
+        Some((username.to_string(), account))
+    }
+
+    fn format(&self, username: &str) -> String {
+        let mut line = format!("{}:{}", username, self.secret);
+        if self.state != AccountState::Active {
+            line.push_str(&format!(":state={}", self.state));
+        }
+        if let Some(user_id) = &self.user_id {
+            line.push_str(&format!(":user_id={}", user_id));
+        }
+        if let Some(metadata_hash) = &self.metadata_hash {
+            line.push_str(&format!(":metadata_hash={}", metadata_hash));
+        }
+        if let Some(relay_list_hash) = &self.relay_list_hash {
+            line.push_str(&format!(":relay_list_hash={}", relay_list_hash));
+        }
+        if let Some(handed_over) = &self.handed_over {
+            line.push_str(&format!(":handed_over={}", handed_over));
+        }
+        for old_secret in &self.old_secrets {
+            line.push_str(&format!(":old_secret={}", old_secret));
+        }
+        line
+    }
+}
+
 pub struct SimpleDatabase {
-    follows: std::collections::HashMap<String, String>,
+    follows: std::collections::HashMap<String, Account>,
     file: String,
 }
 
@@ -23,15 +123,15 @@ impl SimpleDatabase {
         let content = std::fs::read_to_string(path).expect("Failed opening database file");
 
         for line in content.lines() {
-            let split = line.split(':').collect::<Vec<_>>();
-            if split.len() != 2 {
-                debug!("unable to parse line: >{:?}<, skipping", split);
-                continue;
-            }
-            let username = split[0];
-            let seckey = split[1];
+            let (username, account) = match Account::parse(line) {
+                Some(parsed) => parsed,
+                None => {
+                    debug!("unable to parse line: >{}<, skipping", line);
+                    continue;
+                }
+            };
 
-            match db.follows.insert(username.to_string(), seckey.to_string()) {
+            match db.follows.insert(username.clone(), account) {
                 Some(_) => panic!(
                     "Inconsistent database, username {} is more than once in the database",
                     username
@@ -53,22 +153,90 @@ impl SimpleDatabase {
             return Err("Key already in the database".to_string());
         }
 
-        self.follows.insert(username.clone(), seckey.clone());
+        let account = Account::new(seckey);
+        let line = account.format(&username);
+        self.follows.insert(username.clone(), account);
         debug!("Added {} to the database", username);
 
         let mut file = std::fs::OpenOptions::new()
-            .write(true)
             .append(true)
             .open(self.file.clone())
             .unwrap();
 
-        writeln!(file, "{}:{}", username, seckey).unwrap();
+        writeln!(file, "{}", line).unwrap();
         debug!("Wrote updated database to the file");
         Ok(())
     }
 
+    /// Removes `username` and returns its account.
+    pub fn remove(&mut self, username: &str) -> Result<Account, String> {
+        let account = self
+            .follows
+            .remove(username)
+            .ok_or(format!("{} is not in the database", username))?;
+        self.save()?;
+        debug!("Removed {} from the database", username);
+        Ok(account)
+    }
+
+    /// Returns the whole database in its file format.
+    pub fn export(&self) -> String {
+        let mut content = String::new();
+        for (username, account) in self.get_accounts() {
+            content.push_str(&account.format(&username));
+            content.push('\n');
+        }
+        content
+    }
+
+    /// Adds accounts from `content` written by `export`. Accounts already in the database are
+    /// skipped. Returns usernames of the added accounts.
+    pub fn import(&mut self, content: &str) -> Result<Vec<String>, String> {
+        let mut imported = vec![];
+        for (line_number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (username, mut account) =
+                Account::parse(line).ok_or(format!("Unable to parse line {}", line_number + 1))?;
+            // Secrets may be given as nsec but the database keeps them in hex
+            let normalize = |secret: &str| {
+                utils::parse_secret(secret)
+                    .map(|secret| secret.display_secret().to_string())
+                    .map_err(|e| format!("{} on line {}", e, line_number + 1))
+            };
+            account.secret = normalize(&account.secret)?;
+            account.old_secrets = account
+                .old_secrets
+                .iter()
+                .map(|secret| normalize(secret))
+                .collect::<Result<_, _>>()?;
+
+            if self.follows.contains_key(&username) || imported.contains(&username) {
+                warn!("{} is already in the database, skipping", username);
+                continue;
+            }
+            imported.push(username.clone());
+            self.follows.insert(username, account);
+        }
+
+        self.save()?;
+        Ok(imported)
+    }
+
+    /// Returns all accounts sorted by username.
+    pub fn get_accounts(&self) -> Vec<(String, Account)> {
+        let mut accounts = self
+            .follows
+            .iter()
+            .map(|(username, account)| (username.clone(), account.clone()))
+            .collect::<Vec<_>>();
+        accounts.sort_by(|a, b| a.0.cmp(&b.0));
+        accounts
+    }
+
     pub fn get(&self, key: &str) -> String {
-        self.follows.get(key).unwrap().to_string()
+        self.follows.get(key).unwrap().secret.to_string()
     }
 
     pub fn contains_key(&self, key: &str) -> bool {
@@ -78,10 +246,10 @@ impl SimpleDatabase {
     pub fn get_follows(&self) -> std::collections::HashMap<String, secp256k1::KeyPair> {
         let mut result = std::collections::HashMap::<String, secp256k1::KeyPair>::new();
         let secp = secp256k1::Secp256k1::new();
-        for (username, secret) in &self.follows {
+        for (username, account) in &self.follows {
             result.insert(
                 username.clone(),
-                secp256k1::KeyPair::from_seckey_str(&secp, secret).unwrap(),
+                secp256k1::KeyPair::from_seckey_str(&secp, &account.secret).unwrap(),
             );
         }
         result
@@ -90,6 +258,72 @@ impl SimpleDatabase {
     pub fn follows_count(&self) -> usize {
         self.follows.len()
     }
+
+    pub fn get_account(&self, username: &str) -> Option<Account> {
+        self.follows.get(username).cloned()
+    }
+
+    pub fn get_states(&self) -> std::collections::HashMap<String, AccountState> {
+        self.follows
+            .iter()
+            .map(|(username, account)| (username.clone(), account.state.clone()))
+            .collect()
+    }
+
+    pub fn set_state(&mut self, username: &str, state: AccountState) -> Result<(), String> {
+        self.update(username, |account| account.state = state)
+    }
+
+    pub fn set_user_id(&mut self, username: &str, user_id: String) -> Result<(), String> {
+        self.update(username, |account| account.user_id = Some(user_id))
+    }
+
+    pub fn set_metadata_hash(&mut self, username: &str, hash: String) -> Result<(), String> {
+        self.update(username, |account| account.metadata_hash = Some(hash))
+    }
+
+    pub fn set_relay_list_hash(&mut self, username: &str, hash: String) -> Result<(), String> {
+        self.update(username, |account| account.relay_list_hash = Some(hash))
+    }
+
+    pub fn set_handed_over(&mut self, username: &str, owner: String) -> Result<(), String> {
+        self.update(username, |account| account.handed_over = Some(owner))
+    }
+
+    /// Replaces the secret of `username` by `new_secret`, the old one is kept in `old_secrets`.
+    /// Profile and relay list have to be published again for the new key.
+    pub fn rotate_secret(&mut self, username: &str, new_secret: String) -> Result<(), String> {
+        self.update(username, |account| {
+            let old_secret = std::mem::replace(&mut account.secret, new_secret);
+            account.old_secrets.push(old_secret);
+            account.metadata_hash = None;
+            account.relay_list_hash = None;
+        })
+    }
+
+    pub fn is_handed_over(&self, username: &str) -> bool {
+        matches!(self.follows.get(username), Some(account) if account.handed_over.is_some())
+    }
+
+    fn update(&mut self, username: &str, f: impl FnOnce(&mut Account)) -> Result<(), String> {
+        match self.follows.get_mut(username) {
+            Some(account) => f(account),
+            None => return Err(format!("{} is not in the database", username)),
+        }
+        self.save()
+    }
+
+    // Rewrites the whole file. Written to a temporary file first so the database isn't lost if
+    // writing fails halfway.
+    fn save(&self) -> Result<(), String> {
+        let content = self.export();
+        let tmp_file = format!("{}.tmp", self.file);
+        std::fs::write(&tmp_file, content)
+            .and_then(|_| std::fs::rename(&tmp_file, &self.file))
+            .map_err(|e| format!("Unable to write database {}: {}", self.file, e))?;
+        debug!("Wrote updated database to the file");
+        Ok(())
+    }
 }
 
 pub fn get_user_keypair(username: &str, db: Database) -> secp256k1::KeyPair {
@@ -97,3 +331,114 @@ pub fn get_user_keypair(username: &str, db: Database) -> secp256k1::KeyPair {
     let existing_secret = db.lock().unwrap().get(username);
     secp256k1::KeyPair::from_seckey_str(&secp, &existing_secret).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_secrets_roundtrip() {
+        let (current, first, second) = (
+            utils::get_random_keypair(),
+            utils::get_random_keypair(),
+            utils::get_random_keypair(),
+        );
+        let line = format!(
+            "nasa:{}:user_id=11348282:old_secret={}:old_secret={}",
+            current.display_secret(),
+            first.display_secret(),
+            second.display_secret()
+        );
+
+        let (username, account) = Account::parse(&line).unwrap();
+        assert_eq!(username, "nasa");
+        assert_eq!(
+            account.old_secrets,
+            vec![
+                first.display_secret().to_string(),
+                second.display_secret().to_string()
+            ]
+        );
+        assert_eq!(account.keypairs(), Ok(vec![current, first, second]));
+        assert_eq!(account.format(&username), line);
+
+        let broken = format!("nasa:{}:old_secret=invalid", current.display_secret());
+        assert!(Account::parse(&broken).unwrap().1.keypairs().is_err());
+    }
+
+    #[test]
+    fn rotate_secret() {
+        let file = utils::TempFile::new("db");
+        let mut db = SimpleDatabase::from_file(file.path());
+        let (first, second, third) = (
+            utils::get_random_keypair(),
+            utils::get_random_keypair(),
+            utils::get_random_keypair(),
+        );
+        db.insert("nasa".to_string(), first.display_secret().to_string())
+            .unwrap();
+        db.set_relay_list_hash("nasa", "hash".to_string()).unwrap();
+        db.set_metadata_hash("nasa", "hash".to_string()).unwrap();
+
+        db.rotate_secret("nasa", second.display_secret().to_string())
+            .unwrap();
+        db.rotate_secret("nasa", third.display_secret().to_string())
+            .unwrap();
+        assert!(db
+            .rotate_secret("spacex", third.display_secret().to_string())
+            .is_err());
+
+        // Survives restart
+        let db = SimpleDatabase::from_file(file.path());
+        let account = db.get_account("nasa").unwrap();
+        assert_eq!(account.keypairs(), Ok(vec![third, first, second]));
+        assert!(account.relay_list_hash.is_none());
+        assert!(account.metadata_hash.is_none());
+    }
+
+    #[test]
+    fn export_import_roundtrip() {
+        let file = utils::TempFile::new("db");
+        let mut db = SimpleDatabase::from_file(file.path());
+        let keypair = utils::get_random_keypair();
+        db.insert("nasa".to_string(), keypair.display_secret().to_string())
+            .unwrap();
+        db.set_user_id("nasa", "11348282".to_string()).unwrap();
+        db.set_state("nasa", AccountState::Suspended).unwrap();
+
+        let other = utils::get_random_keypair();
+        let exported = format!(
+            "{}spacex:{}\n",
+            db.export(),
+            utils::nsec(&other.secret_key())
+        );
+
+        let imported_file = utils::TempFile::new("db");
+        let mut imported_db = SimpleDatabase::from_file(imported_file.path());
+        assert_eq!(
+            imported_db.import(&exported),
+            Ok(vec!["nasa".to_string(), "spacex".to_string()])
+        );
+
+        let nasa = imported_db.get_account("nasa").unwrap();
+        assert_eq!(nasa.secret, keypair.display_secret().to_string());
+        assert_eq!(nasa.user_id.as_deref(), Some("11348282"));
+        assert_eq!(nasa.state, AccountState::Suspended);
+        // nsec is stored as hex so the rest of tostr can read it
+        assert_eq!(
+            imported_db.get("spacex"),
+            other.display_secret().to_string()
+        );
+        assert_eq!(
+            get_user_keypair(
+                "spacex",
+                std::sync::Arc::new(std::sync::Mutex::new(imported_db))
+            ),
+            other
+        );
+
+        // Existing accounts are skipped, invalid secrets refused
+        assert_eq!(db.import(&exported), Ok(vec!["spacex".to_string()]));
+        assert!(db.import("starlink:nsec1invalid").is_err());
+    }
+}
@@ -2,11 +2,34 @@ use log::{debug, info, warn};
 use std::fmt::Write;
 
 use rand::Rng;
+use secp256k1::hashes::Hash;
 
+use crate::config;
+use crate::dm;
+use crate::forwarded;
+use crate::links;
+use crate::media;
+use crate::preview;
+use crate::proxy;
 use crate::simpledb;
 use crate::twitter;
 use crate::utils;
 
+// How often is checked whether the account wasn't suspended, protected, ...
+const ACCOUNT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+// How often are changes of the Twitter profile (name, bio, pictures, ...) mirrored
+const PROFILE_SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 3600);
+// Accounts that are not active are polled only this often
+const INACTIVE_REFRESH_INTERVAL_SECS: u64 = 6 * 3600;
+// How often are recently forwarded tweets checked for deletion
+const DELETION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+// How long has the owner to tweet the challenge after !claim
+const CLAIM_VALIDITY: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+// Limits the number of claims waiting for a challenge tweet so they can't be used for spamming
+const MAX_PENDING_CLAIMS: usize = 100;
+// Limits the claims of a single claimant so one can't take all the pending slots
+const MAX_CLAIMS_PER_CLAIMANT: usize = 3;
+
 type Receiver = tokio::sync::mpsc::Receiver<ConnectionMessage>;
 type ErrorSender = tokio::sync::mpsc::Sender<ConnectionMessage>;
 
@@ -14,6 +37,7 @@ type ErrorSender = tokio::sync::mpsc::Sender<ConnectionMessage>;
 enum ConnectionStatus {
     Success,
     Failed,
+    RateLimited,
 }
 
 #[derive(Debug)]
@@ -22,13 +46,29 @@ pub struct ConnectionMessage {
     timestamp: std::time::SystemTime,
 }
 
+/// Pending claim of a bridged account by its owner, see [handle_claim].
+pub struct Claim {
+    username: String,
+    claimant: secp256k1::XOnlyPublicKey,
+    challenge: String,
+    expires: std::time::Instant,
+}
+
 pub struct TostrState {
-    pub config: utils::Config,
+    pub config: config::Config,
     pub db: simpledb::Database,
+    pub forwarded: std::sync::Arc<std::sync::Mutex<forwarded::ForwardedTweets>>,
+    pub link_resolver: std::sync::Arc<links::LinkResolver>,
+    pub preview_fetcher: std::sync::Arc<preview::PreviewFetcher>,
+    pub media_rehoster: std::sync::Arc<media::MediaRehoster>,
+    pub proxy: proxy::ProxyPolicy,
+    /// `scratch_dir` from the config or the default one in the data directory.
+    pub scratch_dir: std::path::PathBuf,
     pub sender: nostr_bot::Sender,
+    pub claims: Vec<Claim>,
 
     // error_receiver: tokio::sync::mpsc::Receiver<bot::ConnectionMessage>,
-    pub error_sender: tokio::sync::mpsc::Sender<ConnectionMessage>,
+    pub error_sender: ErrorSender,
 
     pub started_timestamp: u64,
 }
@@ -60,6 +100,12 @@ pub async fn error_listener(
                 ConnectionStatus::Failed => {
                     message_to_send = Some("I can't connect to Twitter right now :(.".to_string());
                 }
+                ConnectionStatus::RateLimited => {
+                    message_to_send = Some(
+                        "Twitter is rate limiting me, tweets will be forwarded with a delay."
+                            .to_string(),
+                    );
+                }
             }
 
             last_accepted_message = message;
@@ -81,6 +127,9 @@ pub async fn error_listener(
                         message_to_send =
                             Some("I'm still unable to connect to Twitter :(".to_string());
                     }
+                    ConnectionStatus::RateLimited => {
+                        message_to_send = Some("Twitter is still rate limiting me :(".to_string());
+                    }
                 }
                 last_accepted_message = message;
             }
@@ -116,7 +165,17 @@ pub async fn handle_relays(
 }
 
 pub async fn handle_list(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
-    let follows = state.lock().await.db.lock().unwrap().get_follows();
+    let (follows, states, handed_over) = {
+        let db = state.lock().await.db.clone();
+        let db = db.lock().unwrap();
+        let follows = db.get_follows();
+        let handed_over = follows
+            .keys()
+            .filter(|username| db.is_handed_over(username))
+            .cloned()
+            .collect::<std::collections::HashSet<_>>();
+        (follows, db.get_states(), handed_over)
+    };
     let mut usernames = follows.keys().collect::<Vec<_>>();
     usernames.sort();
 
@@ -130,7 +189,15 @@ pub async fn handle_list(event: nostr_bot::Event, state: State) -> nostr_bot::Ev
             "p".to_string(),
             secret.x_only_public_key().0.to_string(),
         ]);
-        writeln!(text, "#[{}]", index + orig_tags_count).unwrap();
+        write!(text, "#[{}]", index + orig_tags_count).unwrap();
+        if handed_over.contains(username) {
+            writeln!(text, " (handed over)").unwrap();
+            continue;
+        }
+        match states.get(username) {
+            Some(twitter::AccountState::Active) | None => writeln!(text).unwrap(),
+            Some(account_state) => writeln!(text, " ({})", account_state.description()).unwrap(),
+        }
     }
 
     nostr_bot::EventNonSigned {
@@ -141,8 +208,45 @@ pub async fn handle_list(event: nostr_bot::Event, state: State) -> nostr_bot::Ev
     }
 }
 
+pub async fn handle_status(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
+    let states = state.lock().await.db.lock().unwrap().get_states();
+
+    let words = event.content.split_whitespace().collect::<Vec<_>>();
+    if words.len() > 1 {
+        let username = words[1].to_ascii_lowercase().replace('@', "");
+        let text = match states.get(&username) {
+            Some(account_state) => format!("Hi, @{} is {}.", username, account_state.description()),
+            None => format!("Hi, I'm not following @{}.", username),
+        };
+        return nostr_bot::get_reply(event, text);
+    }
+
+    let mut inactive = states
+        .iter()
+        .filter(|(_, account_state)| **account_state != twitter::AccountState::Active)
+        .collect::<Vec<_>>();
+    inactive.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut text = format!(
+        "Hi, I'm following {} accounts, {} of them are active.",
+        states.len(),
+        states.len() - inactive.len()
+    );
+    for (username, account_state) in inactive {
+        write!(text, "\n@{} is {}", username, account_state.description()).unwrap();
+    }
+
+    nostr_bot::get_reply(event, text)
+}
+
 pub async fn handle_random(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
-    let follows = state.lock().await.db.lock().unwrap().get_follows();
+    let follows = {
+        let db = state.lock().await.db.clone();
+        let db = db.lock().unwrap();
+        let mut follows = db.get_follows();
+        follows.retain(|username, _| !db.is_handed_over(username));
+        follows
+    };
 
     if follows.is_empty() {
         return nostr_bot::get_reply(
@@ -186,8 +290,30 @@ pub async fn handle_add(event: nostr_bot::Event, state: State) -> nostr_bot::Eve
         .to_ascii_lowercase()
         .replace('@', "");
 
+    if !twitter::is_valid_username(&username) {
+        debug!(
+            "Invalid !add command >{}< (invalid username).",
+            event.content
+        );
+        return nostr_bot::get_reply(
+            event,
+            format!("Error: >{}< is not a valid Twitter username.", username),
+        );
+    }
+
     let db = state.lock().await.db.clone();
     let config = state.lock().await.config.clone();
+    let proxy = state.lock().await.proxy.clone();
+
+    if db.lock().unwrap().is_handed_over(&username) {
+        return nostr_bot::get_reply(
+            event,
+            format!(
+                "Hi, @{} was handed over to its owner, I don't forward its tweets anymore.",
+                username
+            ),
+        );
+    }
 
     if db.lock().unwrap().contains_key(&username) {
         let keypair = simpledb::get_user_keypair(&username, db);
@@ -204,11 +330,25 @@ pub async fn handle_add(event: nostr_bot::Event, state: State) -> nostr_bot::Eve
             format!("Hi, sorry, couldn't add new account. I'm already running at my max capacity ({} users).", config.max_follows));
     }
 
-    if !twitter::user_exists(&username).await {
-        return nostr_bot::get_reply(
-            event,
-            format!("Hi, I wasn't able to find {} on Twitter :(.", username),
-        );
+    let scraper_options = twitter::ScraperOptions::new(&config, &proxy);
+    match twitter::user_exists(&username, &scraper_options).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return nostr_bot::get_reply(
+                event,
+                format!("Hi, I wasn't able to find {} on Twitter :(.", username),
+            );
+        }
+        Err(e) => {
+            warn!("Unable to check if {} exists: {}", username, e);
+            return nostr_bot::get_reply(
+                event,
+                format!(
+                    "Hi, I can't check {} on Twitter right now, please try again later.",
+                    username
+                ),
+            );
+        }
     }
 
     let keypair = utils::get_random_keypair();
@@ -225,17 +365,359 @@ pub async fn handle_add(event: nostr_bot::Event, state: State) -> nostr_bot::Eve
     );
 
     {
-        let sender = state.lock().await.sender.clone();
-        let tx = state.lock().await.error_sender.clone();
-        let refresh_interval_secs = config.refresh_interval_secs;
+        let state = state.clone();
         tokio::spawn(async move {
-            update_user(username, &keypair, sender, tx, refresh_interval_secs).await;
+            update_user(username, &keypair, state).await;
         });
     }
 
     get_handle_response(event, &xonly_pubkey.to_string())
 }
 
+/// Sends secret key of a bridged account to its owner by encrypted DM and stops posting with it.
+///
+/// Admins only, the recipient has to be verified by the admin beforehand.
+pub async fn handle_handover(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
+    let (config, db) = {
+        let state = state.lock().await;
+        (state.config.clone(), state.db.clone())
+    };
+
+    if !config.is_admin(&event.pubkey) {
+        return nostr_bot::get_reply(
+            event,
+            "Sorry, only admins can hand accounts over.".to_string(),
+        );
+    }
+
+    let words = event.content.split_whitespace().collect::<Vec<_>>();
+    if words.len() < 3 {
+        return nostr_bot::get_reply(
+            event,
+            "Error: Use '!handover username recipient_npub'.".to_string(),
+        );
+    }
+
+    let username = words[1].to_ascii_lowercase().replace('@', "");
+    let account = match db.lock().unwrap().get_account(&username) {
+        Some(account) => account,
+        None => {
+            return nostr_bot::get_reply(event, format!("Hi, I'm not following @{}.", username))
+        }
+    };
+    if account.handed_over.is_some() {
+        return nostr_bot::get_reply(event, format!("Hi, @{} was already handed over.", username));
+    }
+
+    let recipient = match utils::parse_pubkey(words[2]) {
+        Ok(recipient) => recipient,
+        Err(e) => return nostr_bot::get_reply(event, format!("Error: {}.", e)),
+    };
+
+    let secret = match utils::parse_secret(&account.secret) {
+        Ok(secret) => secret,
+        Err(e) => {
+            warn!("{}", e);
+            return nostr_bot::get_reply(event, format!("Error: {}.", e));
+        }
+    };
+    let account_pubkey = secret
+        .keypair(&secp256k1::Secp256k1::new())
+        .x_only_public_key()
+        .0;
+    let bot_keypair = nostr_bot::keypair_from_secret(&config.secret);
+    if recipient == account_pubkey || recipient == bot_keypair.x_only_public_key().0 {
+        return nostr_bot::get_reply(
+            event,
+            "Error: The recipient has to be the owner's own key.".to_string(),
+        );
+    }
+
+    if let Err(e) = hand_over(&username, &recipient, &state).await {
+        warn!("{}", e);
+        return nostr_bot::get_reply(event, format!("Error: {}.", e));
+    }
+
+    let mut tags = nostr_bot::tags_for_reply(event);
+    tags.push(vec!["p".to_string(), recipient.to_string()]);
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 1,
+        content: format!(
+            "Hi, the key of @{} was sent to #[{}], I don't post with it anymore.",
+            username,
+            tags.len() - 1
+        ),
+        tags,
+    }
+}
+
+/// Lets the owner of a Twitter account claim its bridged nostr identity. Returns a challenge to
+/// tweet from the account, the worker then sends the key to the claimant by encrypted DM.
+pub async fn handle_claim(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
+    let words = event.content.split_whitespace().collect::<Vec<_>>();
+    if words.len() < 2 {
+        return nostr_bot::get_reply(event, "Error: Missing username.".to_string());
+    }
+    let username = words[1].to_ascii_lowercase().replace('@', "");
+
+    let claimant = match event.pubkey.parse::<secp256k1::XOnlyPublicKey>() {
+        Ok(claimant) => claimant,
+        Err(_) => return nostr_bot::get_reply(event, "Error: Invalid public key.".to_string()),
+    };
+
+    let mut state = state.lock().await;
+    let account = state.db.lock().unwrap().get_account(&username);
+    match account {
+        None => {
+            return nostr_bot::get_reply(event, format!("Hi, I'm not following @{}.", username))
+        }
+        Some(account) if account.handed_over.is_some() => {
+            return nostr_bot::get_reply(
+                event,
+                format!("Hi, @{} was already handed over.", username),
+            )
+        }
+        Some(account) if account.state != twitter::AccountState::Active => {
+            return nostr_bot::get_reply(
+                event,
+                format!(
+                    "Hi, @{} is {} so I can't see its tweets, it can't be claimed now.",
+                    username,
+                    account.state.description()
+                ),
+            )
+        }
+        Some(_) => {}
+    }
+
+    let challenge = match add_claim(
+        &mut state.claims,
+        &username,
+        claimant,
+        std::time::Instant::now(),
+    ) {
+        Ok(challenge) => challenge,
+        Err(e) => return nostr_bot::get_reply(event, format!("Hi, {}", e)),
+    };
+
+    // Claims are kept only in memory
+    nostr_bot::get_reply(
+        event,
+        format!(
+            "Hi, to claim @{} tweet {} from the account in the next {} hours. Once I see the tweet \
+            I'll send you the key by encrypted DM and stop posting with it. If I'm restarted \
+            before that the challenge is forgotten and you have to claim the account again.",
+            username,
+            challenge,
+            CLAIM_VALIDITY.as_secs() / 3600
+        ),
+    )
+}
+
+// Returns the challenge of the pending claim of `username` by `claimant`, the claim is added if
+// there's none yet. Fails if there are too many pending claims.
+fn add_claim(
+    claims: &mut Vec<Claim>,
+    username: &str,
+    claimant: secp256k1::XOnlyPublicKey,
+    now: std::time::Instant,
+) -> Result<String, String> {
+    claims.retain(|claim| claim.expires > now);
+
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.username == username && claim.claimant == claimant)
+    {
+        return Ok(claim.challenge.clone());
+    }
+
+    if claims.len() >= MAX_PENDING_CLAIMS {
+        return Err("there are too many pending claims, please try again later.".to_string());
+    }
+    let claimant_claims = claims
+        .iter()
+        .filter(|claim| claim.claimant == claimant)
+        .count();
+    if claimant_claims >= MAX_CLAIMS_PER_CLAIMANT {
+        return Err(format!(
+            "you already have {} pending claims, please wait until they are done or expire.",
+            claimant_claims
+        ));
+    }
+
+    let challenge = format!("tostr-claim-{:016x}", rand::thread_rng().gen::<u64>());
+    claims.push(Claim {
+        username: username.to_string(),
+        claimant,
+        challenge: challenge.clone(),
+        expires: now + CLAIM_VALIDITY,
+    });
+    info!("@{} claimed by {}", username, claimant);
+    Ok(challenge)
+}
+
+// Returns the claimant whose challenge for `username` is in `tweets`. Claims for the account are
+// removed then.
+async fn find_claimant(
+    username: &str,
+    tweets: &[twitter::Tweet],
+    state: &State,
+) -> Option<secp256k1::XOnlyPublicKey> {
+    let mut state = state.lock().await;
+    take_claimant(
+        &mut state.claims,
+        username,
+        |challenge| tweets.iter().any(|tweet| tweet.contains(challenge)),
+        std::time::Instant::now(),
+    )
+}
+
+// Returns the claimant of `username` whose challenge was tweeted and removes all claims of the
+// account then.
+fn take_claimant(
+    claims: &mut Vec<Claim>,
+    username: &str,
+    is_tweeted: impl Fn(&str) -> bool,
+    now: std::time::Instant,
+) -> Option<secp256k1::XOnlyPublicKey> {
+    claims.retain(|claim| claim.expires > now);
+
+    let claimant = claims
+        .iter()
+        .find(|claim| claim.username == username && is_tweeted(&claim.challenge))
+        .map(|claim| claim.claimant)?;
+    claims.retain(|claim| claim.username != username);
+    Some(claimant)
+}
+
+// Sends the secret key of `username` to `recipient` by encrypted DM and marks the account as
+// handed over, its worker stops in the next round.
+async fn hand_over(
+    username: &str,
+    recipient: &secp256k1::XOnlyPublicKey,
+    state: &State,
+) -> Result<(), String> {
+    let (config, db, sender) = {
+        let state = state.lock().await;
+        (state.config.clone(), state.db.clone(), state.sender.clone())
+    };
+
+    let account = db
+        .lock()
+        .unwrap()
+        .get_account(username)
+        .ok_or(format!("@{} is not followed", username))?;
+    let secret = utils::parse_secret(&account.secret)?;
+    let account_pubkey = secret
+        .keypair(&secp256k1::Secp256k1::new())
+        .x_only_public_key()
+        .0;
+    let bot_keypair = nostr_bot::keypair_from_secret(&config.secret);
+
+    let message = format!(
+        "Hi, this is the secret key of {} that bridged tweets of @{}. I don't post with it anymore, \
+        it's all yours now. Keep it safe!\n\n{}",
+        utils::npub(&account_pubkey),
+        username,
+        utils::nsec(&secret)
+    );
+    sender
+        .lock()
+        .await
+        .send(dm::get_dm_event(&bot_keypair, recipient, &message).sign(&bot_keypair))
+        .await;
+
+    let result = db
+        .lock()
+        .unwrap()
+        .set_handed_over(username, recipient.to_string());
+    result.map_err(|e| {
+        format!(
+            "The key was sent but @{} couldn't be marked as handed over: {}",
+            username, e
+        )
+    })?;
+    info!("@{} was handed over to {}", username, recipient);
+    Ok(())
+}
+
+/// Replaces a (possibly compromised) key of a bridged account by a new one. The old key's profile
+/// points to the new one and the old key is kept only for deleting notes it signed.
+///
+/// Admins only.
+pub async fn handle_rotate(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
+    let (config, db, sender) = {
+        let state = state.lock().await;
+        (state.config.clone(), state.db.clone(), state.sender.clone())
+    };
+
+    if !config.is_admin(&event.pubkey) {
+        return nostr_bot::get_reply(event, "Sorry, only admins can rotate keys.".to_string());
+    }
+
+    let words = event.content.split_whitespace().collect::<Vec<_>>();
+    if words.len() < 2 {
+        return nostr_bot::get_reply(event, "Error: Missing username.".to_string());
+    }
+    let username = words[1].to_ascii_lowercase().replace('@', "");
+
+    let account = match db.lock().unwrap().get_account(&username) {
+        Some(account) => account,
+        None => {
+            return nostr_bot::get_reply(event, format!("Hi, I'm not following @{}.", username))
+        }
+    };
+    if account.handed_over.is_some() {
+        return nostr_bot::get_reply(
+            event,
+            format!(
+                "Hi, @{} was handed over, its key isn't mine to rotate.",
+                username
+            ),
+        );
+    }
+
+    let old_keypair = match utils::parse_secret(&account.secret) {
+        Ok(secret) => secret.keypair(&secp256k1::Secp256k1::new()),
+        Err(e) => {
+            warn!("Unable to rotate key of @{}: {}", username, e);
+            return nostr_bot::get_reply(event, format!("Error: {}.", e));
+        }
+    };
+    let new_keypair = utils::get_random_keypair();
+    let (new_pubkey, _parity) = new_keypair.x_only_public_key();
+
+    let result = db
+        .lock()
+        .unwrap()
+        .rotate_secret(&username, new_keypair.display_secret().to_string());
+    if let Err(e) = result {
+        warn!("Unable to rotate key of @{}: {}", username, e);
+        return nostr_bot::get_reply(event, format!("Error: {}.", e));
+    }
+    info!("Key of @{} rotated, new pubkey {}", username, new_pubkey);
+
+    sender
+        .lock()
+        .await
+        .send(get_moved_profile_event(&username, &new_pubkey).sign(&old_keypair))
+        .await;
+
+    let mut tags = nostr_bot::tags_for_reply(event);
+    tags.push(vec!["p".to_string(), new_pubkey.to_string()]);
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 1,
+        content: format!(
+            "Hi, tweets of @{} will be forwarded by #[{}] from now on.",
+            username,
+            tags.len() - 1
+        ),
+        tags,
+    }
+}
+
 pub async fn uptime(event: nostr_bot::Event, state: State) -> nostr_bot::EventNonSigned {
     let uptime_seconds = nostr_bot::unix_timestamp() - state.lock().await.started_timestamp;
     nostr_bot::get_reply(
@@ -264,16 +746,19 @@ fn get_handle_response(event: nostr_bot::Event, new_bot_pubkey: &str) -> nostr_b
 }
 
 pub async fn start_existing(state: State) {
-    let state = state.lock().await;
-    for (username, keypair) in state.db.lock().unwrap().get_follows() {
-        let error_sender = state.error_sender.clone();
+    let db = state.lock().await.db.clone();
+    let follows = db.lock().unwrap().get_follows();
+    for (username, keypair) in follows {
+        if db.lock().unwrap().is_handed_over(&username) {
+            debug!("@{} was handed over, not starting its worker", username);
+            continue;
+        }
         info!("Starting worker for username {}", username);
 
         {
-            let refresh = state.config.refresh_interval_secs;
-            let sender = state.sender.clone();
+            let state = state.clone();
             tokio::spawn(async move {
-                update_user(username, &keypair, sender, error_sender, refresh).await;
+                update_user(username, &keypair, state).await;
             });
         }
     }
@@ -293,56 +778,732 @@ async fn fake_worker(username: String, refresh_interval_secs: u64) {
     }
 }
 
-pub async fn update_user(
-    username: String,
+// Checks the account's state and updates it in the database. Returns None if the state can't be
+// found out right now.
+async fn check_account_state(
+    username: &str,
+    db: &simpledb::Database,
+    scraper_options: &twitter::ScraperOptions,
+) -> Option<twitter::AccountState> {
+    let user_id = db
+        .lock()
+        .unwrap()
+        .get_account(username)
+        .and_then(|account| account.user_id);
+
+    match twitter::get_account_state(username, user_id.as_deref(), scraper_options).await {
+        Ok((account_state, new_user_id)) => {
+            let mut db = db.lock().unwrap();
+            if let Some(new_user_id) = new_user_id {
+                if user_id.as_ref() != Some(&new_user_id) {
+                    if let Err(e) = db.set_user_id(username, new_user_id) {
+                        warn!("{}", e);
+                    }
+                }
+            }
+            if let Err(e) = db.set_state(username, account_state.clone()) {
+                warn!("{}", e);
+            }
+            Some(account_state)
+        }
+        Err(e) => {
+            warn!("Unable to check state of @{}: {}", username, e);
+            None
+        }
+    }
+}
+
+/// Content of kind 0 event, see NIP-01 and NIP-24.
+#[derive(serde::Serialize)]
+struct Metadata {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    about: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    banner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    website: Option<String>,
+    /// Marks the account as automated.
+    bot: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nip05: Option<String>,
+}
+
+fn get_profile_event(
+    username: &str,
+    profile: &twitter::Profile,
+    account_state: &twitter::AccountState,
+    nip05_domain: Option<&str>,
+) -> nostr_bot::EventNonSigned {
+    let mut about = String::new();
+    if !profile.bio.is_empty() {
+        about.push_str(&profile.bio);
+        about.push_str("\n\n");
+    }
+    write!(
+        about,
+        "Tweets forwarded from https://twitter.com/{} by [tostr](https://github.com/slaninas/tostr) bot.",
+        username
+    )
+    .unwrap();
+    match account_state {
+        twitter::AccountState::Active => {}
+        twitter::AccountState::Suspended => {
+            about.push_str(" The account is suspended on Twitter, forwarding is paused.")
+        }
+        twitter::AccountState::Protected => about
+            .push_str(" The account is protected on Twitter, its tweets can't be forwarded."),
+        twitter::AccountState::NotFound => {
+            about.push_str(" The account doesn't exist on Twitter anymore.")
+        }
+        twitter::AccountState::Renamed(new_username) => write!(
+            about,
+            " The account was renamed to https://twitter.com/{}, its tweets are not forwarded here anymore.",
+            new_username
+        )
+        .unwrap(),
+    }
+
+    let metadata = Metadata {
+        name: format!("tostr_{}", username),
+        display_name: Some(profile.name.clone()).filter(|name| !name.is_empty()),
+        about,
+        picture: profile.avatar.clone(),
+        banner: profile.banner.clone(),
+        website: profile.website.clone(),
+        bot: true,
+        nip05: nip05_domain.map(|domain| nip05_identifier(username, domain)),
+    };
+
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 0,
+        tags: vec![],
+        content: serde_json::to_string(&metadata).unwrap(),
+    }
+}
+
+// Profile published on the old key after rotation so followers can find the new one
+fn get_moved_profile_event(
+    username: &str,
+    new_pubkey: &secp256k1::XOnlyPublicKey,
+) -> nostr_bot::EventNonSigned {
+    let metadata = Metadata {
+        name: format!("tostr_{}", username),
+        display_name: None,
+        about: format!(
+            "This key is not used anymore, tweets from https://twitter.com/{} are forwarded by nostr:{} now.",
+            username,
+            utils::npub(new_pubkey)
+        ),
+        picture: None,
+        banner: None,
+        website: None,
+        bot: true,
+        nip05: None,
+    };
+
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 0,
+        tags: vec![],
+        content: serde_json::to_string(&metadata).unwrap(),
+    }
+}
+
+/// NIP-05 identifier of the bridged account, e.g. `elonmusk@example.com`.
+pub fn nip05_identifier(username: &str, domain: &str) -> String {
+    format!("{}@{}", username.to_ascii_lowercase(), domain)
+}
+
+// Re-fetches the profile (only active accounts have any) and publishes it if it differs from the
+// last published one. `profile` keeps the last known profile in case the fetching fails.
+async fn sync_profile(
+    username: &str,
     keypair: &secp256k1::KeyPair,
-    sender: nostr_bot::Sender,
-    tx: ErrorSender,
-    refresh_interval_secs: u64,
+    account_state: &twitter::AccountState,
+    profile: &mut Option<twitter::Profile>,
+    state: &State,
 ) {
-    // fake_worker(username, refresh_interval_secs).await;
-    // return;
+    let (sender, config, db, proxy, scratch_dir) = {
+        let state = state.lock().await;
+        (
+            state.sender.clone(),
+            state.config.clone(),
+            state.db.clone(),
+            state.proxy.clone(),
+            state.scratch_dir.clone(),
+        )
+    };
 
-    let pic_url = twitter::get_pic_url(&username).await;
-    let event = nostr_bot::Event::new(
-        keypair,
-        utils::unix_timestamp(),
-        0,
-        vec![],
-        format!(
-            r#"{{"name":"tostr_{}","about":"Tweets forwarded from https://twitter.com/{} by [tostr](https://github.com/slaninas/tostr) bot.","picture":"{}"}}"#,
-            username, username, pic_url
+    if *account_state == twitter::AccountState::Active {
+        let scraper_options = twitter::ScraperOptions::new(&config, &proxy);
+        match twitter::get_profile(username, &scratch_dir, &scraper_options).await {
+            Ok(fetched) => *profile = Some(fetched),
+            Err(e) => warn!("Unable to get profile of @{}: {}", username, e),
+        }
+    }
+
+    // Don't replace the published profile with an empty one just because Twitter didn't respond,
+    // inactive accounts have to be published even without it to show their state
+    let event = match profile {
+        Some(profile) => get_profile_event(
+            username,
+            profile,
+            account_state,
+            config.nip05_domain.as_deref(),
         ),
-    );
+        None if *account_state != twitter::AccountState::Active => get_profile_event(
+            username,
+            &twitter::Profile::default(),
+            account_state,
+            config.nip05_domain.as_deref(),
+        ),
+        None => return,
+    };
+
+    let hash = secp256k1::hashes::sha256::Hash::hash(event.content.as_bytes()).to_string();
+    let last_hash = db
+        .lock()
+        .unwrap()
+        .get_account(username)
+        .and_then(|account| account.metadata_hash);
+    if last_hash.as_ref() == Some(&hash) {
+        debug!("Profile of @{} didn't change, not publishing it", username);
+        return;
+    }
+
+    info!("Publishing profile of @{}", username);
+    sender.lock().await.send(event.sign(keypair)).await;
+    // Stored so restarts don't publish unchanged profiles again, nostr-bot doesn't tell whether
+    // relays accepted the event so written once it was sent
+    let result = db.lock().unwrap().set_metadata_hash(username, hash);
+    if let Err(e) = result {
+        warn!("{}", e);
+    }
+}
+
+/// NIP-65 relay list, all relays are used for both reading and writing.
+fn get_relay_list_event(relays: &[String]) -> nostr_bot::EventNonSigned {
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 10002,
+        tags: relays
+            .iter()
+            .map(|relay| vec!["r".to_string(), relay.clone()])
+            .collect(),
+        content: "".to_string(),
+    }
+}
+
+fn relay_list_hash(relays: &[String]) -> String {
+    let mut relays = relays.to_vec();
+    relays.sort();
+    secp256k1::hashes::sha256::Hash::hash(relays.join("\n").as_bytes()).to_string()
+}
+
+/// Publishes relay list of the bot itself.
+pub async fn publish_bot_relay_list(keypair: secp256k1::KeyPair, state: State) {
+    let (sender, relays) = {
+        let state = state.lock().await;
+        (state.sender.clone(), state.config.relays.clone())
+    };
+
+    info!("Publishing relay list of the bot");
+    sender
+        .lock()
+        .await
+        .send(get_relay_list_event(&relays).sign(&keypair))
+        .await;
+}
+
+// Publishes relay list of the bridged account unless the same one was already published.
+async fn sync_relay_list(username: &str, keypair: &secp256k1::KeyPair, state: &State) {
+    let (sender, relays, db) = {
+        let state = state.lock().await;
+        (
+            state.sender.clone(),
+            state.config.relays.clone(),
+            state.db.clone(),
+        )
+    };
+
+    let hash = relay_list_hash(&relays);
+    let last_hash = db
+        .lock()
+        .unwrap()
+        .get_account(username)
+        .and_then(|account| account.relay_list_hash);
+    if last_hash.as_ref() == Some(&hash) {
+        debug!("Relays of @{} didn't change, not publishing them", username);
+        return;
+    }
+
+    info!("Publishing relay list of @{}", username);
+    sender
+        .lock()
+        .await
+        .send(get_relay_list_event(&relays).sign(keypair))
+        .await;
+    let result = db.lock().unwrap().set_relay_list_hash(username, hash);
+    if let Err(e) = result {
+        warn!("{}", e);
+    }
+}
+
+// Disconnects from relays that were removed from the config and publishes the new relay lists of
+// the bot and of all accounts it still posts for.
+async fn apply_relay_changes(relays: &[String], keypair: &secp256k1::KeyPair, state: &State) {
+    let (sender, db) = {
+        let state = state.lock().await;
+        (state.sender.clone(), state.db.clone())
+    };
+
+    sender
+        .lock()
+        .await
+        .sinks
+        .retain(|sink| relays.contains(&sink.peer_addr));
+
+    publish_bot_relay_list(*keypair, state.clone()).await;
+
+    let follows = db.lock().unwrap().get_follows();
+    for (username, user_keypair) in follows {
+        if db.lock().unwrap().is_handed_over(&username) {
+            continue;
+        }
+        sync_relay_list(&username, &user_keypair, state).await;
+    }
+}
+
+/// Reloads the config every time SIGHUP is received and applies the changes.
+///
+/// Intervals and limits are applied right away (workers pick up the new values in their next
+/// round), removed relays are disconnected and the relay lists republished. Changing the secret,
+/// `scratch_dir`, `nip05_server` or `proxy` and adding relays requires restart since nostr-bot
+/// can't connect to relays once it's running.
+pub async fn config_reloader(
+    config_path: std::path::PathBuf,
+    keypair: secp256k1::KeyPair,
+    state: State,
+) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!(
+                "Unable to listen for SIGHUP, config reloading is disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Reloading config from {}", config_path.display());
+        let mut new_config = match config::Config::load(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!(
+                    "Keeping the current config, {} is invalid: {}",
+                    config_path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let old_config = state.lock().await.config.clone();
+        let changes = old_config.diff(&new_config);
+        if changes.is_empty() {
+            info!("Config didn't change");
+            continue;
+        }
+        for change in &changes {
+            info!("Config reload: {}", change);
+        }
+
+        if new_config.secret != old_config.secret {
+            warn!("Changing the secret requires restart, keeping the current one");
+            new_config.secret = old_config.secret.clone();
+        }
+        // nostr-bot can only drop connections of a running bot, added relays are kept out of the
+        // config until restart so the published relay lists match the connected relays
+        let added = new_config
+            .relays
+            .iter()
+            .filter(|relay| !old_config.relays.contains(relay))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !added.is_empty() {
+            warn!(
+                "Connecting to added relays requires restart, not using {} until then",
+                added.join(", ")
+            );
+            new_config.relays.retain(|relay| !added.contains(relay));
+        }
+        let relays_changed = new_config.relays != old_config.relays;
+        if new_config.scratch_dir != old_config.scratch_dir {
+            warn!("Changing scratch_dir requires restart, keeping the current one");
+            new_config.scratch_dir = old_config.scratch_dir.clone();
+        }
+        if new_config.nip05_server != old_config.nip05_server {
+            warn!("Changing nip05_server requires restart, keeping the current one");
+            new_config.nip05_server = old_config.nip05_server;
+        }
+        if new_config.proxy != old_config.proxy {
+            warn!("Changing proxy requires restart, keeping the current one");
+            new_config.proxy = old_config.proxy.clone();
+        }
+
+        state.lock().await.config = new_config.clone();
+
+        if relays_changed {
+            apply_relay_changes(&new_config.relays, &keypair, &state).await;
+        }
+
+        if new_config.name != old_config.name
+            || new_config.about != old_config.about
+            || new_config.picture_url != old_config.picture_url
+        {
+            let sender = state.lock().await.sender.clone();
+            sender
+                .lock()
+                .await
+                .send(get_bot_profile_event(&new_config).sign(&keypair))
+                .await;
+        }
+    }
+}
+
+fn get_bot_profile_event(config: &config::Config) -> nostr_bot::EventNonSigned {
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 0,
+        tags: vec![],
+        content: serde_json::json!({
+            "name": config.name,
+            "about": config.about,
+            "picture": config.picture_url,
+        })
+        .to_string(),
+    }
+}
+
+fn get_deletion_event(event_id: &str) -> nostr_bot::EventNonSigned {
+    nostr_bot::EventNonSigned {
+        created_at: utils::unix_timestamp(),
+        kind: 5,
+        tags: vec![vec!["e".to_string(), event_id.to_string()]],
+        content: "The tweet was deleted.".to_string(),
+    }
+}
+
+// Checks tweets of `username` forwarded within the deletion window and deletes (NIP-09) notes of
+// the ones that weren't found by several checks in a row.
+async fn mirror_deletions(
+    username: &str,
+    config: &config::Config,
+    scraper_options: &twitter::ScraperOptions,
+    state: &State,
+) {
+    let (db, forwarded, sender, scratch_dir) = {
+        let state = state.lock().await;
+        (
+            state.db.clone(),
+            state.forwarded.clone(),
+            state.sender.clone(),
+            state.scratch_dir.clone(),
+        )
+    };
+
+    let window_start = utils::unix_timestamp().saturating_sub(config.deletion_window_secs);
+    let result = forwarded.lock().unwrap().prune(window_start);
+    if let Err(e) = result {
+        warn!("{}", e);
+    }
+
+    let recent = forwarded.lock().unwrap().get(username);
+    let oldest = match recent.iter().map(|tweet| tweet.tweet_timestamp).min() {
+        Some(oldest) => oldest,
+        None => return,
+    };
+
+    // A minute back, --since may not be exactly the same as the tweet's time
+    let since = std::time::UNIX_EPOCH + std::time::Duration::from_secs(oldest.saturating_sub(60));
+    let existing = match twitter::get_tweet_ids(
+        username,
+        since.into(),
+        std::time::SystemTime::now().into(),
+        &scratch_dir,
+        scraper_options,
+    )
+    .await
+    {
+        Ok(existing) => existing,
+        Err(e) => {
+            warn!("Unable to check deleted tweets of @{}: {}", username, e);
+            return;
+        }
+    };
+    let result = forwarded.lock().unwrap().check(username, &existing);
+    let missing = match result {
+        Ok(missing) => missing,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+    if missing.is_empty() {
+        return;
+    }
+
+    let keypairs = match db.lock().unwrap().get_account(username) {
+        Some(account) => match account.keypairs() {
+            Ok(keypairs) => keypairs,
+            Err(e) => {
+                warn!("Unable to mirror deletions of @{}: {}", username, e);
+                return;
+            }
+        },
+        None => return,
+    };
+
+    let mut deleted = vec![];
+    for tweet in &missing {
+        if db.lock().unwrap().is_handed_over(username) {
+            info!("@{} was handed over, not mirroring its deletions", username);
+            break;
+        }
 
-    sender.lock().await.send(event).await;
+        match keypairs
+            .iter()
+            .find(|keypair| keypair.x_only_public_key().0.to_string() == tweet.pubkey)
+        {
+            Some(keypair) => {
+                info!(
+                    "Tweet {} of @{} was deleted, deleting note {}",
+                    tweet.tweet_id, username, tweet.event_id
+                );
+                sender
+                    .lock()
+                    .await
+                    .send(get_deletion_event(&tweet.event_id).sign(keypair))
+                    .await;
+            }
+            None => warn!(
+                "Key that signed note {} of @{} is not known, can't delete it",
+                tweet.event_id, username
+            ),
+        }
+        deleted.push(tweet.event_id.clone());
+    }
+
+    if !deleted.is_empty() {
+        let result = forwarded.lock().unwrap().remove(&deleted);
+        if let Err(e) = result {
+            warn!("{}", e);
+        }
+    }
+}
+
+// Whether the account is still posted for with `keypair`, it may have been handed over or its
+// key rotated while the worker was fetching tweets.
+fn can_post(username: &str, keypair: &secp256k1::KeyPair, db: &simpledb::Database) -> bool {
+    match db.lock().unwrap().get_account(username) {
+        Some(account) => {
+            account.handed_over.is_none()
+                && matches!(account.keypairs(), Ok(keypairs) if keypairs.first() == Some(keypair))
+        }
+        None => false,
+    }
+}
+
+pub async fn update_user(username: String, keypair: &secp256k1::KeyPair, state: State) {
+    // The key may be rotated while the worker is running
+    let mut keypair = *keypair;
+    let (sender, tx, config, link_resolver, preview_fetcher, media_rehoster, proxy) = {
+        let state = state.lock().await;
+        (
+            state.sender.clone(),
+            state.error_sender.clone(),
+            state.config.clone(),
+            state.link_resolver.clone(),
+            state.preview_fetcher.clone(),
+            state.media_rehoster.clone(),
+            state.proxy.clone(),
+        )
+    };
+    let scraper_options = twitter::ScraperOptions::new(&config, &proxy);
+
+    // fake_worker(username, config.refresh_interval_secs).await;
+    // return;
+
+    let db = state.lock().await.db.clone();
+    let forwarded = state.lock().await.forwarded.clone();
+    let scratch_dir = state.lock().await.scratch_dir.clone();
+
+    let mut account_state = match db.lock().unwrap().get_account(&username) {
+        Some(account) => account.state,
+        None => twitter::AccountState::Active,
+    };
+    if let Some(checked) = check_account_state(&username, &db, &scraper_options).await {
+        account_state = checked;
+    }
+    let mut next_check = std::time::Instant::now() + ACCOUNT_CHECK_INTERVAL;
+
+    let mut profile = None;
+    sync_profile(&username, &keypair, &account_state, &mut profile, &state).await;
+    sync_relay_list(&username, &keypair, &state).await;
+    let mut next_profile_sync = std::time::Instant::now() + PROFILE_SYNC_INTERVAL;
+    let mut next_deletion_check = std::time::Instant::now() + DELETION_CHECK_INTERVAL;
 
     let mut since: chrono::DateTime<chrono::offset::Local> = std::time::SystemTime::now().into();
 
     loop {
+        // Config may have been reloaded since the last round
+        let config = state.lock().await.config.clone();
+        let scraper_options = twitter::ScraperOptions::new(&config, &proxy);
+
+        // There is nothing to forward from inactive accounts, just check their state from time
+        // to time
+        let sleep_secs = if account_state == twitter::AccountState::Active {
+            config.refresh_interval_secs
+        } else {
+            INACTIVE_REFRESH_INTERVAL_SECS
+        };
         debug!(
-            "Worker for @{} is going to sleep for {} s",
-            username, refresh_interval_secs
+            "Worker for @{} ({}) is going to sleep for {} s",
+            username, account_state, sleep_secs
         );
-        tokio::time::sleep(std::time::Duration::from_secs(refresh_interval_secs)).await;
+        tokio::time::sleep(std::time::Duration::from_secs(sleep_secs)).await;
+
+        if db.lock().unwrap().is_handed_over(&username) {
+            info!("@{} was handed over, stopping its worker", username);
+            return;
+        }
+
+        let current_keypair = simpledb::get_user_keypair(&username, db.clone());
+        if current_keypair != keypair {
+            info!(
+                "Key of @{} was rotated, forwarding as {} now",
+                username,
+                current_keypair.x_only_public_key().0
+            );
+            keypair = current_keypair;
+            sync_profile(&username, &keypair, &account_state, &mut profile, &state).await;
+            sync_relay_list(&username, &keypair, &state).await;
+        }
+
+        if account_state != twitter::AccountState::Active || std::time::Instant::now() >= next_check
+        {
+            next_check = std::time::Instant::now() + ACCOUNT_CHECK_INTERVAL;
+
+            if let Some(new_state) = check_account_state(&username, &db, &scraper_options).await {
+                if new_state != account_state {
+                    info!(
+                        "State of @{} changed from {} to {}",
+                        username, account_state, new_state
+                    );
+                    sync_profile(&username, &keypair, &new_state, &mut profile, &state).await;
+
+                    // Tweets from the time the account was inactive can't be fetched anyway
+                    if new_state == twitter::AccountState::Active {
+                        since = std::time::SystemTime::now().into();
+                    }
+                    account_state = new_state;
+                }
+            }
+
+            if account_state != twitter::AccountState::Active {
+                continue;
+            }
+        }
+
+        if config.deletion_window_secs > 0 && std::time::Instant::now() >= next_deletion_check {
+            next_deletion_check = std::time::Instant::now() + DELETION_CHECK_INTERVAL;
+            mirror_deletions(&username, &config, &scraper_options, &state).await;
+        }
+
+        if std::time::Instant::now() >= next_profile_sync {
+            next_profile_sync = std::time::Instant::now() + PROFILE_SYNC_INTERVAL;
+            sync_profile(&username, &keypair, &account_state, &mut profile, &state).await;
+        }
 
         let until = std::time::SystemTime::now().into();
-        let new_tweets = twitter::get_new_tweets(&username, since, until).await;
+        let new_tweets = twitter::get_new_tweets(
+            &username,
+            since,
+            until,
+            &link_resolver,
+            &scratch_dir,
+            &scraper_options,
+        )
+        .await;
 
         match new_tweets {
-            Ok(new_tweets) => {
+            Ok(mut new_tweets) => {
                 // --since seems to be inclusive and --until exclusive so this should be fine
                 since = until;
 
                 // twint returns newest tweets first, reverse the Vec here so that tweets are send to relays
                 // in order they were published. Still the created_at field can easily be the same so in the
                 // end it depends on how the relays handle it
-                for tweet in new_tweets.iter().rev() {
-                    sender
-                        .lock()
-                        .await
-                        .send(twitter::get_tweet_event(tweet).sign(keypair))
-                        .await;
+                let bridged = state.lock().await.db.lock().unwrap().get_follows();
+                for tweet in new_tweets.iter_mut().rev() {
+                    if !can_post(&username, &keypair, &db) {
+                        info!(
+                            "@{} was handed over or its key rotated, not forwarding the rest of its tweets",
+                            username
+                        );
+                        break;
+                    }
+
+                    if let Some(media_server) = &config.media_server {
+                        media_rehoster
+                            .rehost(&mut tweet.media, media_server, &keypair)
+                            .await;
+                    }
+
+                    if config.link_previews {
+                        if let Some(link) = tweet.article_link() {
+                            tweet.preview = preview_fetcher.fetch(&link).await;
+                        }
+                    }
+
+                    // Rehosting and previews take a while, check again before signing
+                    if !can_post(&username, &keypair, &db) {
+                        info!(
+                            "@{} was handed over or its key rotated, not forwarding the rest of its tweets",
+                            username
+                        );
+                        break;
+                    }
+
+                    let event =
+                        twitter::get_tweet_event(tweet, &bridged, config.preserve_tweet_time)
+                            .sign(&keypair);
+
+                    // Remembered so the note can be deleted together with the tweet
+                    if config.deletion_window_secs > 0 {
+                        let result = forwarded.lock().unwrap().insert(
+                            &username,
+                            forwarded::Forwarded {
+                                tweet_id: tweet.id().to_string(),
+                                event_id: event.id.clone(),
+                                pubkey: event.pubkey.clone(),
+                                tweet_timestamp: tweet.timestamp(),
+                                misses: 0,
+                            },
+                        );
+                        if let Err(e) = result {
+                            warn!("{}", e);
+                        }
+                    }
+
+                    sender.lock().await.send(event).await;
                 }
 
                 tx.send(ConnectionMessage {
@@ -351,17 +1512,232 @@ pub async fn update_user(
                 })
                 .await
                 .unwrap();
+
+                if let Some(claimant) = find_claimant(&username, &new_tweets, &state).await {
+                    match hand_over(&username, &claimant, &state).await {
+                        Ok(()) => {
+                            info!(
+                                "@{} was claimed by its owner, stopping its worker",
+                                username
+                            );
+                            return;
+                        }
+                        Err(e) => warn!("Unable to hand over @{}: {}", username, e),
+                    }
+                }
             }
             Err(e) => {
-                tx.send(ConnectionMessage {
-                    status: ConnectionStatus::Failed,
-                    timestamp: std::time::SystemTime::now(),
-                })
-                .await
-                .unwrap();
-                warn!("{}", e);
+                warn!("Unable to get new tweets from {}: {}", username, e);
+
+                match e {
+                    // Problems with the connection to Twitter affect all workers
+                    // A hung scraper is most likely waiting for the connection too
+                    twitter::FetchError::Network(_)
+                    | twitter::FetchError::ProcessFailed(_)
+                    | twitter::FetchError::Timeout(_) => {
+                        tx.send(ConnectionMessage {
+                            status: ConnectionStatus::Failed,
+                            timestamp: std::time::SystemTime::now(),
+                        })
+                        .await
+                        .unwrap();
+                    }
+                    twitter::FetchError::RateLimited => {
+                        tx.send(ConnectionMessage {
+                            status: ConnectionStatus::RateLimited,
+                            timestamp: std::time::SystemTime::now(),
+                        })
+                        .await
+                        .unwrap();
+                    }
+                    // Account specific, nothing to report to everyone, just find out what
+                    // happened to the account during the next round
+                    twitter::FetchError::UserNotFound | twitter::FetchError::UserSuspended => {
+                        next_check = std::time::Instant::now();
+                    }
+                    // Retrying the same interval would most likely fail again, skip it
+                    twitter::FetchError::Parse(_) => since = until,
+                }
             }
         }
         // break;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pubkey() -> secp256k1::XOnlyPublicKey {
+        utils::get_random_keypair().x_only_public_key().0
+    }
+
+    fn metadata(event: &nostr_bot::EventNonSigned) -> serde_json::Value {
+        serde_json::from_str(&event.content).unwrap()
+    }
+
+    #[test]
+    fn profile_metadata() {
+        let profile = twitter::Profile {
+            name: "NASA".to_string(),
+            bio: "Exploring the universe".to_string(),
+            avatar: Some("https://pbs.twimg.com/profile_images/1/nasa.jpg".to_string()),
+            banner: None,
+            website: Some("https://nasa.gov".to_string()),
+        };
+        let event = get_profile_event(
+            "NASA",
+            &profile,
+            &twitter::AccountState::Active,
+            Some("example.com"),
+        );
+        assert_eq!(event.kind, 0);
+
+        let metadata = metadata(&event);
+        assert_eq!(metadata["name"], "tostr_NASA");
+        assert_eq!(metadata["display_name"], "NASA");
+        assert!(metadata["about"].as_str().unwrap().starts_with(
+            "Exploring the universe\n\nTweets forwarded from https://twitter.com/NASA"
+        ));
+        assert_eq!(
+            metadata["picture"],
+            "https://pbs.twimg.com/profile_images/1/nasa.jpg"
+        );
+        assert_eq!(metadata["website"], "https://nasa.gov");
+        assert_eq!(metadata["bot"], true);
+        assert_eq!(metadata["nip05"], "nasa@example.com");
+        assert!(metadata.get("banner").is_none());
+    }
+
+    #[test]
+    fn profile_metadata_omits_empty() {
+        let event = get_profile_event(
+            "nasa",
+            &twitter::Profile::default(),
+            &twitter::AccountState::Renamed("nasa2".to_string()),
+            None,
+        );
+
+        let metadata = metadata(&event);
+        for field in ["display_name", "picture", "banner", "website", "nip05"] {
+            assert!(metadata.get(field).is_none(), "{} is set", field);
+        }
+        assert_eq!(metadata["bot"], true);
+        let about = metadata["about"].as_str().unwrap();
+        assert!(about.starts_with("Tweets forwarded from"));
+        assert!(about.contains("renamed to https://twitter.com/nasa2"));
+    }
+
+    #[test]
+    fn relay_list() {
+        let relays = vec![
+            "wss://relay.example.com".to_string(),
+            "wss://nos.example.org".to_string(),
+        ];
+        let event = get_relay_list_event(&relays);
+
+        assert_eq!(event.kind, 10002);
+        assert!(event.content.is_empty());
+        // Without a marker the relay is used for both reading and writing (NIP-65)
+        assert_eq!(
+            event.tags,
+            vec![
+                vec!["r".to_string(), "wss://relay.example.com".to_string()],
+                vec!["r".to_string(), "wss://nos.example.org".to_string()],
+            ]
+        );
+
+        // Order doesn't matter, the same relays aren't published again
+        let reversed = relays.iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(relay_list_hash(&relays), relay_list_hash(&reversed));
+        assert_ne!(relay_list_hash(&relays), relay_list_hash(&relays[..1]));
+    }
+
+    #[test]
+    fn claims_are_limited() {
+        let mut claims = vec![];
+        let now = std::time::Instant::now();
+        let (alice, bob) = (pubkey(), pubkey());
+
+        let challenge = add_claim(&mut claims, "nasa", alice, now).unwrap();
+        assert_eq!(add_claim(&mut claims, "nasa", alice, now), Ok(challenge));
+        assert_eq!(claims.len(), 1);
+
+        for username in ["spacex", "esa"] {
+            add_claim(&mut claims, username, alice, now).unwrap();
+        }
+        assert!(add_claim(&mut claims, "jaxa", alice, now).is_err());
+        // Others aren't affected
+        add_claim(&mut claims, "jaxa", bob, now).unwrap();
+
+        // Expired claims don't count
+        let later = now + CLAIM_VALIDITY + std::time::Duration::from_secs(1);
+        add_claim(&mut claims, "jaxa", alice, later).unwrap();
+        assert_eq!(claims.len(), 1);
+
+        let mut claims = (0..MAX_PENDING_CLAIMS)
+            .map(|i| Claim {
+                username: format!("user{}", i),
+                claimant: pubkey(),
+                challenge: String::new(),
+                expires: now + CLAIM_VALIDITY,
+            })
+            .collect::<Vec<_>>();
+        assert!(add_claim(&mut claims, "nasa", alice, now).is_err());
+    }
+
+    #[test]
+    fn claimant_is_found_by_challenge() {
+        let mut claims = vec![];
+        let now = std::time::Instant::now();
+        let (alice, bob) = (pubkey(), pubkey());
+        let alice_challenge = add_claim(&mut claims, "nasa", alice, now).unwrap();
+        add_claim(&mut claims, "nasa", bob, now).unwrap();
+        let spacex_challenge = add_claim(&mut claims, "spacex", alice, now).unwrap();
+
+        let tweeted = |challenge: &str| challenge == alice_challenge;
+        assert_eq!(take_claimant(&mut claims, "spacex", tweeted, now), None);
+        assert_eq!(claims.len(), 3);
+
+        // Other claims of the account are dropped
+        assert_eq!(
+            take_claimant(&mut claims, "nasa", tweeted, now),
+            Some(alice)
+        );
+        assert_eq!(claims.len(), 1);
+
+        // Expired challenges don't count even if tweeted
+        let later = now + CLAIM_VALIDITY;
+        let tweeted = |challenge: &str| challenge == spacex_challenge;
+        assert_eq!(take_claimant(&mut claims, "spacex", tweeted, later), None);
+        assert!(claims.is_empty());
+    }
+
+    #[test]
+    fn stops_posting_after_rotation_or_handover() {
+        let file = utils::TempFile::new("db");
+        let db = simpledb::Database::new(std::sync::Mutex::new(
+            simpledb::SimpleDatabase::from_file(file.path()),
+        ));
+        let (first, second) = (utils::get_random_keypair(), utils::get_random_keypair());
+        db.lock()
+            .unwrap()
+            .insert("nasa".to_string(), first.display_secret().to_string())
+            .unwrap();
+        assert!(can_post("nasa", &first, &db));
+        assert!(!can_post("spacex", &first, &db));
+
+        db.lock()
+            .unwrap()
+            .rotate_secret("nasa", second.display_secret().to_string())
+            .unwrap();
+        assert!(!can_post("nasa", &first, &db));
+        assert!(can_post("nasa", &second, &db));
+
+        db.lock()
+            .unwrap()
+            .set_handed_over("nasa", pubkey().to_string())
+            .unwrap();
+        assert!(!can_post("nasa", &second, &db));
+    }
+}
@@ -1,185 +1,1393 @@
-use log::{debug, info};
+use log::{debug, info, warn};
+use rand::Rng;
+use std::fmt::Write;
 
+use crate::config;
+use crate::links;
+use crate::media;
+use crate::preview;
+use crate::proxy;
 use crate::utils;
 
 const DATE_FORMAT_STR: &str = "%Y-%m-%d %H:%M:%S";
+// Part of the name of every file twint writes tweets into
+const WORKFILE_MARK: &str = "_workfile";
 
 pub struct Tweet {
+    id: String,
     timestamp: u64,
     tweet: String,
     link: String,
+    pub media: Vec<media::Media>,
+    pub preview: Option<preview::Preview>,
 }
 
-pub fn get_tweet_event(tweet: &Tweet) -> nostr_bot::EventNonSigned {
-    let formatted = format!("{} ([source]({}))", tweet.tweet, tweet.link);
+impl Tweet {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-    nostr_bot::EventNonSigned {
-        created_at: utils::unix_timestamp(),
-        kind: 1,
-        tags: vec![vec![
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn contains(&self, text: &str) -> bool {
+        self.tweet.contains(text)
+    }
+
+    /// Returns first link in the tweet that doesn't point to Twitter itself.
+    pub fn article_link(&self) -> Option<String> {
+        let is_twitter = |url: &url::Url| {
+            matches!(
+                url.host_str(),
+                Some("twitter.com" | "www.twitter.com" | "mobile.twitter.com" | "t.co")
+                    | Some("pic.twitter.com" | "x.com" | "www.x.com")
+            )
+        };
+
+        linkify::LinkFinder::new()
+            .links(&self.tweet)
+            .filter_map(|link| url::Url::parse(link.as_str()).ok())
+            .find(|url| !is_twitter(url))
+            .map(|url| url.to_string())
+    }
+}
+
+/// Returns kind 1 event for the `tweet`. `bridged` are accounts followed by tostr, mentions of
+/// them are turned into nostr mentions. If `preserve_time` is set the event's created_at is set
+/// to the time the tweet was posted instead of now.
+pub fn get_tweet_event(
+    tweet: &Tweet,
+    bridged: &std::collections::HashMap<String, secp256k1::KeyPair>,
+    preserve_time: bool,
+) -> nostr_bot::EventNonSigned {
+    let rehosted = tweet
+        .media
+        .iter()
+        .filter(|m| m.is_rehosted())
+        .collect::<Vec<_>>();
+
+    // Links to the photos on Twitter are replaced by the re-hosted copies, one link points to all
+    // the photos so it's kept if any of them failed to be re-hosted
+    let text = if !rehosted.is_empty() && rehosted.len() == tweet.media.len() {
+        strip_photo_links(&tweet.tweet)
+    } else {
+        tweet.tweet.clone()
+    };
+    let (text, entity_tags) = link_entities(&text, bridged);
+
+    let mut formatted = format!("{} ([source]({}))", text, tweet.link);
+    let mut tags = vec![
+        vec![
             "tweet_timestamp".to_string(),
             format!("{}", tweet.timestamp),
-        ]],
+        ],
+        // NIP-48, let clients know the note is bridged from Twitter
+        vec!["proxy".to_string(), tweet.link.clone(), "web".to_string()],
+    ];
+    tags.extend(entity_tags);
+
+    if let Some(preview) = &tweet.preview {
+        write!(formatted, "\n\n> {}", preview.title).unwrap();
+        if let Some(description) = &preview.description {
+            write!(formatted, "\n> {}", description).unwrap();
+        }
+        tags.push(vec!["r".to_string(), preview.url.clone()]);
+        // NIP-92 imeta describes only urls in the content so the image goes there too
+        if let Some(image) = &preview.image {
+            write!(formatted, "\n{}", image).unwrap();
+            tags.push(vec!["imeta".to_string(), format!("url {}", image)]);
+        }
+    }
+
+    for media in rehosted {
+        formatted.push('\n');
+        formatted.push_str(&media.url);
+
+        // NIP-92 media attachment
+        let mut imeta = vec!["imeta".to_string(), format!("url {}", media.url)];
+        if let Some(sha256) = &media.sha256 {
+            imeta.push(format!("x {}", sha256));
+        }
+        if let Some(mime) = &media.mime {
+            imeta.push(format!("m {}", mime));
+        }
+        tags.push(imeta);
+    }
+
+    let created_at = if preserve_time {
+        tweet.timestamp
+    } else {
+        utils::unix_timestamp()
+    };
+
+    nostr_bot::EventNonSigned {
+        created_at,
+        kind: 1,
+        tags,
         content: formatted,
     }
 }
 
-pub async fn user_exists(username: &String) -> bool {
-    let since: chrono::DateTime<chrono::offset::Local> = std::time::SystemTime::now().into();
+/// Finds #hashtags and @mentions in the `text`. Hashtags are returned as `t` tags, mentions of
+/// `bridged` accounts are replaced by NIP-27 references and returned as `p` tags, other mentions
+/// are replaced by links to their Twitter profiles.
+fn link_entities(
+    text: &str,
+    bridged: &std::collections::HashMap<String, secp256k1::KeyPair>,
+) -> (String, Vec<Vec<String>>) {
+    // Don't look for entities inside of links, e.g. https://example.com/@user or url#fragment
+    let links = linkify::LinkFinder::new()
+        .links(text)
+        .map(|link| (link.start(), link.end()))
+        .collect::<Vec<_>>();
+    let in_link = |pos: usize| links.iter().any(|(start, end)| pos >= *start && pos < *end);
+
+    let mut result = String::new();
+    let mut hashtags = vec![];
+    let mut pubkeys = vec![];
+    let mut prev: Option<char> = None;
+    let mut pos = 0;
+
+    while let Some(c) = text[pos..].chars().next() {
+        let at_word_start = !matches!(prev, Some(p) if p.is_alphanumeric() || p == '_');
+
+        if (c == '#' || c == '@') && at_word_start && !in_link(pos) {
+            let start = pos + c.len_utf8();
+            let is_word_char = |ch: char| {
+                if c == '#' {
+                    ch.is_alphanumeric() || ch == '_'
+                } else {
+                    ch.is_ascii_alphanumeric() || ch == '_'
+                }
+            };
+            let end = text[start..]
+                .char_indices()
+                .find(|(_, ch)| !is_word_char(*ch))
+                .map_or(text.len(), |(i, _)| start + i);
+            let word = &text[start..end];
+
+            if c == '#' && !word.is_empty() && !word.chars().all(|ch| ch.is_ascii_digit()) {
+                let hashtag = word.to_lowercase();
+                if !hashtags.contains(&hashtag) {
+                    hashtags.push(hashtag);
+                }
+                result.push_str(&text[pos..end]);
+                prev = word.chars().last();
+                pos = end;
+                continue;
+            }
+
+            if c == '@' && !word.is_empty() && word.len() <= 15 {
+                match bridged.get(&word.to_ascii_lowercase()) {
+                    Some(keypair) => {
+                        let (pubkey, _parity) = keypair.x_only_public_key();
+                        write!(result, "nostr:{}", utils::npub(&pubkey)).unwrap();
+                        if !pubkeys.contains(&pubkey) {
+                            pubkeys.push(pubkey);
+                        }
+                    }
+                    None => {
+                        write!(result, "[@{}](https://twitter.com/{})", word, word).unwrap();
+                    }
+                }
+                prev = word.chars().last();
+                pos = end;
+                continue;
+            }
+        }
+
+        result.push(c);
+        prev = Some(c);
+        pos += c.len_utf8();
+    }
 
-    let cmd = format!(
-        "twint -u '{}' --since \"{}\" 1>/dev/null",
-        username,
-        since.format(DATE_FORMAT_STR),
+    let mut tags = hashtags
+        .into_iter()
+        .map(|hashtag| vec!["t".to_string(), hashtag])
+        .collect::<Vec<_>>();
+    tags.extend(
+        pubkeys
+            .into_iter()
+            .map(|pubkey| vec!["p".to_string(), pubkey.to_string()]),
     );
-    debug!("Running >{}<", cmd);
-    let status = async_process::Command::new("bash")
-        .arg("-c")
-        .arg(cmd)
-        .status()
-        .await
-        .unwrap();
 
-    status.success()
+    (result, tags)
 }
 
-pub async fn get_pic_url(username: &String) -> String {
-    let pic_cmd = format!(
-        r#"twint --user-full -u '{}' 2>&1 | sed 's/.*Avatar: \(https.*\)/\1/' | tr -d \\n"#,
-        username
-    );
-    debug!("Runnings bash -c '{}", pic_cmd);
+fn strip_photo_links(text: &str) -> String {
+    let is_photo_link = |word: &str| {
+        word.contains("pic.twitter.com/")
+            || (word.contains("twitter.com/") && word.contains("/photo/"))
+    };
+
+    text.lines()
+        .map(|line| {
+            line.split(' ')
+                .filter(|word| !is_photo_link(word))
+                .collect::<Vec<_>>()
+                .join(" ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+// twint stores lists as python lists, e.g. ['https://pbs.twimg.com/media/a.jpg', '...']
+fn parse_list(field: &str) -> Vec<String> {
+    field
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|item| item.trim().trim_matches('\'').to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    /// Scraper couldn't be started or failed for unknown reason.
+    ProcessFailed(String),
+    RateLimited,
+    UserSuspended,
+    UserNotFound,
+    Parse(ParseError),
+    Network(String),
+    /// Scraper didn't finish in time and was killed.
+    Timeout(std::time::Duration),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FetchError::ProcessFailed(e) => write!(f, "scraper failed: {}", e),
+            FetchError::RateLimited => write!(f, "rate limited by Twitter"),
+            FetchError::UserSuspended => write!(f, "account is suspended"),
+            FetchError::UserNotFound => write!(f, "account doesn't exist"),
+            FetchError::Parse(e) => write!(f, "unable to parse scraper output: {}", e),
+            FetchError::Network(e) => write!(f, "network error: {}", e),
+            FetchError::Timeout(timeout) => {
+                write!(f, "scraper didn't finish in {} s", timeout.as_secs())
+            }
+        }
+    }
+}
 
-    let stdout = async_process::Command::new("bash")
-        .arg("-c")
-        .arg(pic_cmd)
-        .output()
-        .await
-        .expect("twint command failed")
-        .stdout;
+impl From<ParseError> for FetchError {
+    fn from(e: ParseError) -> Self {
+        FetchError::Parse(e)
+    }
+}
 
-    let pic_url = String::from_utf8(stdout).unwrap();
+// twint doesn't have any meaningful exit codes so the reason is guessed from its errors. Only its
+// own error lines (`[!] ...`) from stdout are looked at, the rest of stdout are tweets that may
+// contain anything.
+fn classify_failure(stdout: &str, stderr: &str) -> FetchError {
+    let errors = stdout
+        .lines()
+        .filter(|line| line.trim_start().starts_with("[!]"))
+        .chain(stderr.lines())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let lowercase = errors.to_lowercase();
+    let contains_any = |patterns: &[&str]| patterns.iter().any(|p| lowercase.contains(p));
 
-    if pic_url.starts_with("http") {
-        debug!("Found pic url {} for {}", pic_url, username);
-        pic_url
+    if contains_any(&[
+        "account is suspended",
+        "user is suspended",
+        "has been suspended",
+        "account suspended",
+    ]) {
+        FetchError::UserSuspended
+    } else if contains_any(&[
+        "cannot find twitter account",
+        "user not found",
+        "account does not exist",
+        "user does not exist",
+    ]) {
+        FetchError::UserNotFound
+    } else if contains_any(&[
+        "rate limit",
+        "too many requests",
+        "status 429",
+        "status: 429",
+    ]) {
+        FetchError::RateLimited
+    } else if contains_any(&[
+        "cannot connect to host",
+        "clientconnectorerror",
+        "connectionerror",
+        "timeouterror",
+        "temporary failure in name resolution",
+        "proxyerror",
+    ]) {
+        FetchError::Network(last_line(&errors))
     } else {
-        info!("Unable to find picture for {}", username);
-        "".to_string()
+        FetchError::ProcessFailed(last_line(&errors))
     }
 }
 
-pub async fn get_new_tweets(
-    username: &String,
-    since: chrono::DateTime<chrono::offset::Local>,
-    until: chrono::DateTime<chrono::offset::Local>,
-) -> Result<Vec<Tweet>, String> {
-    debug!("Checking new tweets from {}", username);
-    let workfile = format!("{}_workfile.csv", username);
-    let twint_date_format = "%Y-%m-%d %T %z";
+fn last_line(text: &str) -> String {
+    text.lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("")
+        .to_string()
+}
 
-    let cmd = format!(
-        "twint -u '{}' --since \"{}\" --until \"{}\" --csv -o {} 1>/dev/null",
-        username,
-        since.format(DATE_FORMAT_STR),
-        until.format(DATE_FORMAT_STR),
-        workfile
-    );
-    debug!("Running >{}<", cmd);
-    // TODO: Handle status
-    let output = async_process::Command::new("bash")
-        .arg("-c")
-        .arg(cmd)
-        .status()
-        .await
-        .unwrap();
+/// Settings shared by all twint runs.
+#[derive(Clone, Debug)]
+pub struct ScraperOptions {
+    /// twint is killed if it doesn't finish in this time.
+    pub timeout: std::time::Duration,
+    /// socks5 proxy twint connects through.
+    pub proxy: Option<String>,
+}
 
-    if !output.success() {
-        return Err(format!("Unable to check for new tweets from {}", username));
+impl ScraperOptions {
+    pub fn new(config: &config::Config, proxy: &proxy::ProxyPolicy) -> ScraperOptions {
+        ScraperOptions {
+            timeout: std::time::Duration::from_secs(config.scraper_timeout_secs),
+            proxy: proxy.sources.clone(),
+        }
     }
 
-    let mut new_tweets = vec![];
-    match std::fs::read_to_string(workfile.clone()) {
-        Ok(content) => {
-            std::fs::remove_file(workfile).unwrap();
+    /// Arguments that make twint use the proxy, empty if there is no (usable) proxy.
+    pub fn proxy_args(&self) -> Vec<&str> {
+        match self.proxy.as_ref().and_then(|proxy| proxy.rsplit_once(':')) {
+            Some((host, port)) => vec![
+                "--proxy-type",
+                "socks5",
+                "--proxy-host",
+                host,
+                "--proxy-port",
+                port,
+            ],
+            None => vec![],
+        }
+    }
+}
 
-            let csv = content.lines().collect::<Vec<_>>();
+// twint runs in its own process group so that it can be killed together with anything it spawned.
+// The group is killed when this is dropped, i.e. on timeout or when the worker running it is
+// cancelled, unless twint finished on its own.
+struct ProcessGroup {
+    pgid: libc::pid_t,
+    finished: bool,
+}
 
-            for item in csv.iter().skip(1) {
-                let line = item.split('\t').collect::<Vec<_>>();
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if !self.finished {
+            debug!("Killing process group {}", self.pgid);
+            // SAFETY: just sends a signal, the group was created for the spawned twint
+            unsafe {
+                libc::killpg(self.pgid, libc::SIGKILL);
+            }
+        }
+    }
+}
 
-                let tweet = line[10].to_string();
-                // Filter out replies
-                if tweet.starts_with('@') {
-                    debug!("Ignoring reply >{}< from {}", tweet, username);
-                    continue;
-                }
+// Runs twint directly (without shell) so nothing in `args` can be interpreted as a command.
+// Returns twint's stdout.
+async fn run_twint(args: &[&str], options: &ScraperOptions) -> Result<String, FetchError> {
+    use async_process::unix::CommandExt;
 
-                let timestamp = chrono::DateTime::parse_from_str(
-                    &format!("{} {} {}", line[3], line[4], line[5]),
-                    twint_date_format,
-                )
-                .unwrap()
-                .timestamp() as u64;
-                new_tweets.push(Tweet {
-                    timestamp,
-                    tweet,
-                    link: line[20].to_string(),
-                });
+    debug!("Running twint {:?}", args);
+    let mut command = async_process::Command::new("twint");
+    command
+        .args(options.proxy_args())
+        .args(args)
+        .stdout(async_process::Stdio::piped())
+        .stderr(async_process::Stdio::piped())
+        // Child is reaped by async_process once killed
+        .kill_on_drop(true);
+    // SAFETY: setpgid is async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+
+    let child = command
+        .spawn()
+        .map_err(|e| FetchError::ProcessFailed(e.to_string()))?;
+    let mut group = ProcessGroup {
+        pgid: child.id() as libc::pid_t,
+        finished: false,
+    };
+
+    let output = match tokio::time::timeout(options.timeout, child.output()).await {
+        Ok(output) => output.map_err(|e| FetchError::ProcessFailed(e.to_string()))?,
+        Err(_) => {
+            warn!("twint {:?} timed out after {:?}", args, options.timeout);
+            return Err(FetchError::Timeout(options.timeout));
+        }
+    };
+    group.finished = true;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(classify_failure(&stdout, &stderr))
+    }
+}
+
+/// Checks `username` follows Twitter's rules for usernames (1-15 letters, digits and
+/// underscores).
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 15
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountState {
+    Active,
+    Suspended,
+    /// Account's tweets are visible only to its followers.
+    Protected,
+    NotFound,
+    /// Holds the new username.
+    Renamed(String),
+}
+
+impl AccountState {
+    /// Human readable state used in replies, `Display` is the format stored in the database.
+    pub fn description(&self) -> String {
+        match self {
+            AccountState::NotFound => "not found".to_string(),
+            AccountState::Renamed(new_username) => format!("renamed to @{}", new_username),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for AccountState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AccountState::Active => write!(f, "active"),
+            AccountState::Suspended => write!(f, "suspended"),
+            AccountState::Protected => write!(f, "protected"),
+            AccountState::NotFound => write!(f, "not_found"),
+            AccountState::Renamed(new_username) => write!(f, "renamed@{}", new_username),
+        }
+    }
+}
+
+impl std::str::FromStr for AccountState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(AccountState::Active),
+            "suspended" => Ok(AccountState::Suspended),
+            "protected" => Ok(AccountState::Protected),
+            "not_found" => Ok(AccountState::NotFound),
+            _ => match s.strip_prefix("renamed@") {
+                Some(new_username) => Ok(AccountState::Renamed(new_username.to_string())),
+                None => Err(format!("Unknown account state >{}<", s)),
+            },
+        }
+    }
+}
+
+pub struct AccountInfo {
+    pub user_id: String,
+    pub username: String,
+    pub protected: bool,
+}
+
+/// Returns info about the account, `lookup` is either username or user id.
+async fn get_account_info(
+    lookup: &[&str],
+    options: &ScraperOptions,
+) -> Result<AccountInfo, FetchError> {
+    let mut args = vec!["--user-full"];
+    args.extend(lookup);
+
+    let stdout = run_twint(&args, options).await?;
+    match stdout.lines().find_map(parse_user_line) {
+        Some(info) => Ok(info),
+        None => Err(classify_failure(&stdout, "")),
+    }
+}
+
+// Parses twint's user output, e.g.
+// 44196397 | Elon Musk | @elonmusk | Private: False | Verified: True | Bio: ... | Avatar: https://...
+fn parse_user_line(line: &str) -> Option<AccountInfo> {
+    let (user_id, rest) = line.split_once(" | ")?;
+    if user_id.is_empty() || !user_id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let username_start = rest.find(" | @")? + " | @".len();
+    let username = rest[username_start..].split(" | ").next()?;
+
+    let private_start = rest.find("| Private: ")? + "| Private: ".len();
+    let private = rest[private_start..].split(" |").next()?.trim();
+
+    Some(AccountInfo {
+        user_id: user_id.to_string(),
+        username: username.to_string(),
+        protected: private == "True" || private == "1",
+    })
+}
+
+/// Finds out in what state the account is. If the account is not found and its `user_id` is known
+/// it's looked up by the id to check whether it was renamed.
+///
+/// Returns the state together with the account's user id (if it's known).
+pub async fn get_account_state(
+    username: &str,
+    user_id: Option<&str>,
+    options: &ScraperOptions,
+) -> Result<(AccountState, Option<String>), FetchError> {
+    match get_account_info(&["-u", username], options).await {
+        Ok(info) if info.protected => Ok((AccountState::Protected, Some(info.user_id))),
+        Ok(info) => Ok((AccountState::Active, Some(info.user_id))),
+        Err(FetchError::UserSuspended) => {
+            Ok((AccountState::Suspended, user_id.map(|id| id.to_string())))
+        }
+        Err(FetchError::UserNotFound) => {
+            let user_id = match user_id {
+                Some(user_id) => user_id,
+                None => return Ok((AccountState::NotFound, None)),
+            };
+
+            match get_account_info(&["--userid", user_id], options).await {
+                Ok(info) if !info.username.eq_ignore_ascii_case(username) => Ok((
+                    AccountState::Renamed(info.username.to_ascii_lowercase()),
+                    Some(info.user_id),
+                )),
+                Ok(_) | Err(FetchError::UserNotFound) => {
+                    Ok((AccountState::NotFound, Some(user_id.to_string())))
+                }
+                Err(FetchError::UserSuspended) => {
+                    Ok((AccountState::Suspended, Some(user_id.to_string())))
+                }
+                Err(e) => Err(e),
             }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns false if the account doesn't exist or is suspended.
+pub async fn user_exists(username: &str, options: &ScraperOptions) -> Result<bool, FetchError> {
+    let since: chrono::DateTime<chrono::offset::Local> = std::time::SystemTime::now().into();
+    let since = since.format(DATE_FORMAT_STR).to_string();
+
+    match run_twint(&["-u", username, "--since", &since], options).await {
+        Ok(_) => Ok(true),
+        Err(FetchError::UserNotFound | FetchError::UserSuspended) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Profile of the Twitter account, mirrored to the bridged nostr profile.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub bio: String,
+    pub avatar: Option<String>,
+    pub banner: Option<String>,
+    pub website: Option<String>,
+}
+
+pub async fn get_profile(
+    username: &str,
+    scratch_dir: &std::path::Path,
+    options: &ScraperOptions,
+) -> Result<Profile, FetchError> {
+    debug!("Fetching profile of {}", username);
+    let workfile = Workfile::new(scratch_dir, username);
+    let workfile_path = workfile.path.to_string_lossy();
+
+    let stdout = run_twint(
+        &["--user-full", "-u", username, "--csv", "-o", &workfile_path],
+        options,
+    )
+    .await?;
+
+    match std::fs::read_to_string(&workfile.path) {
+        Ok(content) => match parse_profile_csv(&content)? {
+            Some(profile) => Ok(profile),
+            None => Err(classify_failure(&stdout, "")),
+        },
+        Err(_) => Err(classify_failure(&stdout, "")),
+    }
+}
+
+/// Parses output of `twint --user-full --csv`, returns None if there is no user in it.
+pub fn parse_profile_csv(content: &str) -> Result<Option<Profile>, ParseError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_reader(content.as_bytes());
 
-            info!("Found {} new tweets from {}", new_tweets.len(), username);
+    let header = reader.headers()?.clone();
+    let find = |name: &'static str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or(ParseError::MissingColumn(name))
+    };
+    let (name, bio, avatar, banner, website) = (
+        find("name")?,
+        find("bio")?,
+        find("profile_image_url")?,
+        find("background_image")?,
+        find("url")?,
+    );
+
+    let record = match reader.records().next() {
+        Some(record) => record?,
+        None => return Ok(None),
+    };
+    let url =
+        |index: usize| Some(record[index].trim().to_string()).filter(|u| u.starts_with("http"));
+
+    Ok(Some(Profile {
+        name: record[name].to_string(),
+        bio: record[bio].to_string(),
+        avatar: url(avatar),
+        banner: url(banner),
+        website: url(website),
+    }))
+}
+
+// File twint writes its output to, it's removed when dropped so nothing is left behind even
+// when the fetching fails
+struct Workfile {
+    path: std::path::PathBuf,
+}
+
+impl Workfile {
+    fn new(scratch_dir: &std::path::Path, username: &str) -> Workfile {
+        let suffix = rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect::<String>();
+
+        Workfile {
+            path: scratch_dir.join(format!("{}{}{}.csv", username, WORKFILE_MARK, suffix)),
         }
-        Err(_) => {
-            info!("No new tweets from {} found", username);
+    }
+}
+
+impl Drop for Workfile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => debug!("Removed {}", self.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to remove {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Creates `scratch_dir` if needed and removes workfiles left there by previous runs.
+pub fn sweep_workfiles(scratch_dir: &std::path::Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(scratch_dir)?;
+
+    for entry in std::fs::read_dir(scratch_dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.contains(WORKFILE_MARK) && name.ends_with(".csv") {
+            info!("Removing leftover workfile {}", entry.path().display());
+            if let Err(e) = std::fs::remove_file(entry.path()) {
+                warn!("Unable to remove {}: {}", entry.path().display(), e);
+            }
         }
     }
 
+    Ok(())
+}
+
+pub async fn get_new_tweets(
+    username: &str,
+    since: chrono::DateTime<chrono::offset::Local>,
+    until: chrono::DateTime<chrono::offset::Local>,
+    link_resolver: &links::LinkResolver,
+    scratch_dir: &std::path::Path,
+    options: &ScraperOptions,
+) -> Result<Vec<Tweet>, FetchError> {
+    debug!("Checking new tweets from {}", username);
+    let mut new_tweets = get_tweets(username, since, until, scratch_dir, options).await?;
+
+    // Filter out replies
+    new_tweets.retain(|tweet| {
+        let is_reply = tweet.tweet.starts_with('@');
+        if is_reply {
+            debug!("Ignoring reply >{}< from {}", tweet.tweet, username);
+        }
+        !is_reply
+    });
+    info!("Found {} new tweets from {}", new_tweets.len(), username);
+
     // Follow links to the final destinations
-    follow_links(&mut new_tweets).await;
+    follow_links(&mut new_tweets, link_resolver).await;
 
     Ok(new_tweets)
 }
 
-async fn follow_links(tweets: &mut Vec<Tweet>) {
-    let finder = linkify::LinkFinder::new();
+/// Returns ids of all tweets (including replies) `username` posted between `since` and `until`.
+pub async fn get_tweet_ids(
+    username: &str,
+    since: chrono::DateTime<chrono::offset::Local>,
+    until: chrono::DateTime<chrono::offset::Local>,
+    scratch_dir: &std::path::Path,
+    options: &ScraperOptions,
+) -> Result<std::collections::HashSet<String>, FetchError> {
+    let tweets = get_tweets(username, since, until, scratch_dir, options).await?;
+    Ok(tweets.into_iter().map(|tweet| tweet.id).collect())
+}
+
+async fn get_tweets(
+    username: &str,
+    since: chrono::DateTime<chrono::offset::Local>,
+    until: chrono::DateTime<chrono::offset::Local>,
+    scratch_dir: &std::path::Path,
+    options: &ScraperOptions,
+) -> Result<Vec<Tweet>, FetchError> {
+    let workfile = Workfile::new(scratch_dir, username);
 
-    for tweet in tweets {
-        let text = &tweet.tweet;
-        let links: Vec<_> = finder.links(text).collect();
-
-        let mut curr_pos = 0;
-        let mut final_tweet = String::new();
-
-        for link in &links {
-            let start = link.start();
-            let end = link.end();
-
-            let request = reqwest::get(link.as_str()).await;
-
-            let final_url = match request {
-                Ok(response) => response.url().as_str().to_string(),
-                Err(e) => {
-                    debug!(
-                        "Failed to follow link >{}< ({}), using orignal url",
-                        link.as_str().to_string(),
-                        e
-                    );
-                    link.as_str().to_string()
+    let since = since.format(DATE_FORMAT_STR).to_string();
+    let until = until.format(DATE_FORMAT_STR).to_string();
+    let workfile_path = workfile.path.to_string_lossy();
+    run_twint(
+        &[
+            "-u",
+            username,
+            "--since",
+            &since,
+            "--until",
+            &until,
+            "--csv",
+            "-o",
+            &workfile_path,
+        ],
+        options,
+    )
+    .await?;
+
+    let mut tweets = vec![];
+    match std::fs::read_to_string(&workfile.path) {
+        Ok(content) => {
+            let rows = parse_twint_csv(&content)?;
+
+            for row in rows {
+                match row {
+                    Ok(tweet) => tweets.push(tweet),
+                    Err(e) => warn!("Skipping malformed tweet from {}: {}", username, e),
                 }
-            };
+            }
+        }
+        Err(_) => {
+            debug!("No tweets from {} found", username);
+        }
+    }
+
+    Ok(tweets)
+}
 
-            final_tweet.push_str(&text[curr_pos..start]);
-            final_tweet.push_str(&final_url);
-            curr_pos = end;
+#[derive(Debug)]
+pub enum ParseError {
+    /// The row couldn't be read, e.g. it has wrong number of fields.
+    Csv(csv::Error),
+    /// Column required by tostr is not present in the header.
+    MissingColumn(&'static str),
+    InvalidDate {
+        row: usize,
+        value: String,
+    },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::Csv(e) => write!(f, "{}", e),
+            ParseError::MissingColumn(column) => write!(f, "missing column >{}<", column),
+            ParseError::InvalidDate { row, value } => {
+                write!(f, "invalid date >{}< in row {}", value, row)
+            }
         }
+    }
+}
+
+impl From<csv::Error> for ParseError {
+    fn from(e: csv::Error) -> Self {
+        ParseError::Csv(e)
+    }
+}
+
+// Positions of the columns tostr uses in twint's output
+struct Columns {
+    id: usize,
+    date: usize,
+    time: usize,
+    timezone: usize,
+    tweet: usize,
+    link: usize,
+    photos: usize,
+}
+
+impl Columns {
+    fn from_header(header: &csv::StringRecord) -> Result<Columns, ParseError> {
+        let find = |name: &'static str| {
+            header
+                .iter()
+                .position(|column| column == name)
+                .ok_or(ParseError::MissingColumn(name))
+        };
+
+        Ok(Columns {
+            id: find("id")?,
+            date: find("date")?,
+            time: find("time")?,
+            timezone: find("timezone")?,
+            tweet: find("tweet")?,
+            link: find("link")?,
+            photos: find("photos")?,
+        })
+    }
+}
+
+/// Parses tab separated output of `twint --csv`.
+///
+/// Returns error if the header is unusable, otherwise result for every row. One malformed row
+/// doesn't prevent the others from being parsed.
+pub fn parse_twint_csv(content: &str) -> Result<Vec<Result<Tweet, ParseError>>, ParseError> {
+    let twint_date_format = "%Y-%m-%d %T %z";
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_reader(content.as_bytes());
+    let columns = Columns::from_header(reader.headers()?)?;
+
+    let rows = reader
+        .records()
+        .enumerate()
+        .map(|(index, record)| {
+            let record = record?;
+
+            let date = format!(
+                "{} {} {}",
+                &record[columns.date], &record[columns.time], &record[columns.timezone]
+            );
+            let timestamp = chrono::DateTime::parse_from_str(&date, twint_date_format)
+                .map_err(|_| ParseError::InvalidDate {
+                    row: index + 1,
+                    value: date,
+                })?
+                .timestamp() as u64;
 
-        final_tweet.push_str(&text[curr_pos..]);
+            Ok(Tweet {
+                id: record[columns.id].to_string(),
+                timestamp,
+                tweet: record[columns.tweet].to_string(),
+                link: record[columns.link].to_string(),
+                media: parse_list(&record[columns.photos])
+                    .into_iter()
+                    .map(media::Media::new)
+                    .collect(),
+                preview: None,
+            })
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+async fn follow_links(tweets: &mut Vec<Tweet>, link_resolver: &links::LinkResolver) {
+    for tweet in tweets {
+        let final_tweet = link_resolver.expand(&tweet.tweet).await;
 
         debug!(
             "follow_links: orig. tweet >{}<, final tweet >{}<",
-            text, final_tweet
+            tweet.tweet, final_tweet
         );
         tweet.tweet = final_tweet;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(content: &str) -> Vec<Tweet> {
+        parse_twint_csv(content)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_simple() {
+        let tweets = parse_ok(include_str!("../tests/fixtures/twint/simple.csv"));
+        assert_eq!(tweets.len(), 3);
+
+        assert_eq!(
+            tweets[0].tweet,
+            "Starship update soon https://t.co/abcDEF123 #SpaceX"
+        );
+        assert_eq!(
+            tweets[0].link,
+            "https://twitter.com/elonmusk/status/1561000000000000003"
+        );
+        // 2022-08-20 14:03:11 +0200
+        assert_eq!(tweets[0].id, "1561000000000000003");
+        assert_eq!(tweets[0].timestamp, 1660996991);
+        assert!(tweets[0].media.is_empty());
+
+        // Replies are filtered later, the parser returns them
+        assert_eq!(tweets[1].tweet, "@NASA Congrats!");
+
+        let photos = tweets[2]
+            .media
+            .iter()
+            .map(|m| m.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            photos,
+            vec![
+                "https://pbs.twimg.com/media/FaBcDeFXkAAb1cd.jpg",
+                "https://pbs.twimg.com/media/FaBcDeGXoAEa2ef.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_multiline() {
+        let tweets = parse_ok(include_str!("../tests/fixtures/twint/multiline.csv"));
+        assert_eq!(tweets.len(), 2);
+        assert_eq!(
+            tweets[0].tweet,
+            "First line\nsecond line with a \"quote\"\tand a tab\n\nlast line"
+        );
+        assert_eq!(
+            tweets[1].tweet,
+            "Plain tweet, with commas, and 'single quotes'"
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(parse_ok(include_str!("../tests/fixtures/twint/empty.csv")).is_empty());
+    }
+
+    #[test]
+    fn parse_malformed_rows() {
+        let rows = parse_twint_csv(include_str!("../tests/fixtures/twint/malformed.csv")).unwrap();
+        assert_eq!(rows.len(), 3);
+
+        assert_eq!(rows[0].as_ref().unwrap().tweet, "This one is fine");
+        assert!(matches!(
+            rows[1],
+            Err(ParseError::InvalidDate { row: 2, .. })
+        ));
+        assert!(matches!(rows[2], Err(ParseError::Csv(_))));
+    }
+
+    #[test]
+    fn parse_user() {
+        let info = parse_user_line("44196397 | Elon Musk | @elonmusk | Private: False | Verified: True | Bio: Mars & Cars | Location:  | Url:  | Joined: 2 Jun 2009 8:12 PM | Tweets: 19041 | Following: 114 | Followers: 103500000 | Likes: 15000 | Media: 1500 | Avatar: https://pbs.twimg.com/profile_images/1/a.jpg").unwrap();
+        assert_eq!(info.user_id, "44196397");
+        assert_eq!(info.username, "elonmusk");
+        assert!(!info.protected);
+
+        let info = parse_user_line("12 | A | B | @someone | Private: True | Avatar: ").unwrap();
+        assert_eq!(info.username, "someone");
+        assert!(info.protected);
+
+        assert!(parse_user_line("[!] No more data!").is_none());
+    }
+
+    #[test]
+    fn parse_profile() {
+        let profile = parse_profile_csv(include_str!("../tests/fixtures/twint/user.csv"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.name, "NASA");
+        assert_eq!(
+            profile.bio,
+            "Exploring the universe and our home planet.\n\"Verified\" account"
+        );
+        assert_eq!(
+            profile.avatar.as_deref(),
+            Some("https://pbs.twimg.com/profile_images/1/nasa.jpg")
+        );
+        assert_eq!(
+            profile.banner.as_deref(),
+            Some("https://pbs.twimg.com/profile_banners/11348282/1")
+        );
+        // Empty url column, the profile has no website
+        assert!(profile.website.is_none());
+
+        let header = include_str!("../tests/fixtures/twint/user.csv")
+            .lines()
+            .next()
+            .unwrap();
+        assert!(parse_profile_csv(header).unwrap().is_none());
+    }
+
+    #[test]
+    fn link_entities_mentions() {
+        let keypair = utils::get_random_keypair();
+        let pubkey = keypair.x_only_public_key().0;
+        let bridged = std::collections::HashMap::from([("nasa".to_string(), keypair)]);
+
+        let (text, tags) = link_entities("Congrats @NASA and @SpaceX!", &bridged);
+        assert_eq!(
+            text,
+            format!(
+                "Congrats nostr:{} and [@SpaceX](https://twitter.com/SpaceX)!",
+                utils::npub(&pubkey)
+            )
+        );
+        assert_eq!(tags, vec![vec!["p".to_string(), pubkey.to_string()]]);
+
+        // Mentioned twice, tagged once
+        let (_, tags) = link_entities("@nasa @nasa", &bridged);
+        assert_eq!(tags.len(), 1);
+    }
+
+    #[test]
+    fn link_entities_hashtags() {
+        let bridged = std::collections::HashMap::new();
+
+        let (text, tags) = link_entities("Go #Artemis! #artemis #1 #mars_2030", &bridged);
+        assert_eq!(text, "Go #Artemis! #artemis #1 #mars_2030");
+        assert_eq!(
+            tags,
+            vec![
+                vec!["t".to_string(), "artemis".to_string()],
+                vec!["t".to_string(), "mars_2030".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn link_entities_punctuation() {
+        let bridged = std::collections::HashMap::new();
+
+        let (text, tags) = link_entities("(@nasa), @esa. \"@jaxa\"", &bridged);
+        assert_eq!(
+            text,
+            "([@nasa](https://twitter.com/nasa)), [@esa](https://twitter.com/esa). \
+            \"[@jaxa](https://twitter.com/jaxa)\""
+        );
+        assert!(tags.is_empty());
+
+        // Not mentions: e-mails, links and too long handles
+        let input = "mail me@example.com https://example.com/@nasa#top @abcdefghijklmnopq";
+        let (text, tags) = link_entities(input, &bridged);
+        assert_eq!(text, input);
+        assert!(tags.is_empty());
+    }
+
+    fn tweet(text: &str, media: Vec<media::Media>) -> Tweet {
+        Tweet {
+            id: "1561000000000000003".to_string(),
+            timestamp: 1660996991,
+            tweet: text.to_string(),
+            link: "https://twitter.com/nasa/status/1561000000000000003".to_string(),
+            media,
+            preview: None,
+        }
+    }
+
+    fn rehosted(url: &str) -> media::Media {
+        media::Media {
+            url: url.to_string(),
+            sha256: Some("abc".to_string()),
+            mime: Some("image/jpeg".to_string()),
+        }
+    }
+
+    fn tags_of<'a>(event: &'a nostr_bot::EventNonSigned, name: &str) -> Vec<&'a [String]> {
+        event
+            .tags
+            .iter()
+            .filter(|tag| tag[0] == name)
+            .map(|tag| &tag[1..])
+            .collect()
+    }
+
+    #[test]
+    fn tweet_event() {
+        let keypair = utils::get_random_keypair();
+        let pubkey = keypair.x_only_public_key().0;
+        let bridged = std::collections::HashMap::from([("spacex".to_string(), keypair)]);
+
+        let mut tweet = tweet(
+            "Liftoff with @SpaceX #Artemis https://example.com/a https://pic.twitter.com/abc",
+            vec![rehosted("https://media.example.com/1.jpg")],
+        );
+        tweet.preview = Some(preview::Preview {
+            url: "https://example.com/a".to_string(),
+            title: "Artemis I".to_string(),
+            description: Some("Around the Moon".to_string()),
+            image: Some("https://example.com/a.jpg".to_string()),
+        });
+        let event = get_tweet_event(&tweet, &bridged, false);
+
+        assert_eq!(event.kind, 1);
+        assert_eq!(
+            event.content,
+            format!(
+                "Liftoff with nostr:{} #Artemis https://example.com/a \
+                ([source](https://twitter.com/nasa/status/1561000000000000003))\n\n\
+                > Artemis I\n> Around the Moon\nhttps://example.com/a.jpg\n\
+                https://media.example.com/1.jpg",
+                utils::npub(&pubkey)
+            )
+        );
+        assert_eq!(
+            tags_of(&event, "proxy"),
+            vec![["https://twitter.com/nasa/status/1561000000000000003", "web"]]
+        );
+        assert_eq!(tags_of(&event, "t"), vec![["artemis"]]);
+        assert_eq!(tags_of(&event, "p"), vec![[pubkey.to_string()]]);
+        assert_eq!(tags_of(&event, "r"), vec![["https://example.com/a"]]);
+        assert_eq!(
+            tags_of(&event, "imeta"),
+            vec![
+                vec!["url https://example.com/a.jpg".to_string()],
+                vec![
+                    "url https://media.example.com/1.jpg".to_string(),
+                    "x abc".to_string(),
+                    "m image/jpeg".to_string()
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn tweet_event_time() {
+        let bridged = std::collections::HashMap::new();
+        let tweet = tweet("Hello", vec![]);
+
+        let before = utils::unix_timestamp();
+        let event = get_tweet_event(&tweet, &bridged, false);
+        assert!(event.created_at >= before);
+        // Tweet's time is kept in the tag either way
+        assert_eq!(tags_of(&event, "tweet_timestamp"), vec![["1660996991"]]);
+
+        let event = get_tweet_event(&tweet, &bridged, true);
+        assert_eq!(event.created_at, 1660996991);
+        // NIP-48
+        assert_eq!(
+            tags_of(&event, "proxy"),
+            vec![["https://twitter.com/nasa/status/1561000000000000003", "web"]]
+        );
+    }
+
+    #[test]
+    fn tweet_event_keeps_photo_link_if_rehosting_failed() {
+        let bridged = std::collections::HashMap::new();
+        let photos = vec![
+            rehosted("https://media.example.com/1.jpg"),
+            media::Media::new("https://pbs.twimg.com/media/2.jpg".to_string()),
+        ];
+        let event = get_tweet_event(
+            &tweet("Two photos https://pic.twitter.com/abc", photos),
+            &bridged,
+            false,
+        );
+
+        assert!(event
+            .content
+            .starts_with("Two photos https://pic.twitter.com/abc ([source]"));
+        assert!(event.content.ends_with("\nhttps://media.example.com/1.jpg"));
+        assert_eq!(tags_of(&event, "imeta").len(), 1);
+    }
+
+    #[test]
+    fn strip_photo_links_keeps_lines() {
+        assert_eq!(
+            strip_photo_links("Launch! https://pic.twitter.com/abc"),
+            "Launch!"
+        );
+        assert_eq!(
+            strip_photo_links("First line\nhttps://pic.twitter.com/abc\nlast https://x.com/a"),
+            "First line\n\nlast https://x.com/a"
+        );
+        assert_eq!(
+            strip_photo_links("Look\nhttps://twitter.com/nasa/status/1/photo/1"),
+            "Look"
+        );
+    }
+
+    #[test]
+    fn classify_errors() {
+        assert!(matches!(
+            classify_failure(
+                "",
+                "ValueError: Cannot find twitter account with name = nasa"
+            ),
+            FetchError::UserNotFound
+        ));
+        assert!(matches!(
+            classify_failure("[!] Error: User is suspended", ""),
+            FetchError::UserSuspended
+        ));
+        assert!(matches!(
+            classify_failure("", "aiohttp: 429, message='Too Many Requests'"),
+            FetchError::RateLimited
+        ));
+
+        let stderr = "Traceback (most recent call last):\n  ...\n\
+            aiohttp.client_exceptions.ClientConnectorError: Cannot connect to host twitter.com:443\n";
+        match classify_failure("", stderr) {
+            FetchError::Network(e) => assert!(e.starts_with("aiohttp.client_exceptions")),
+            e => panic!("unexpected {:?}", e),
+        }
+
+        match classify_failure("", "Traceback\nKeyError: 'data'\n") {
+            FetchError::ProcessFailed(e) => assert_eq!(e, "KeyError: 'data'"),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn classify_ignores_tweets() {
+        // Tweets in stdout may say anything
+        let stdout = "1561 2022-08-20 14:03:11 +0200 <nasa> Our proxy account was suspended, 429 \
+            days since the user does not exist anymore\n[!] No more data! Scraping will stop now.";
+        match classify_failure(stdout, "KeyError: 'data'") {
+            FetchError::ProcessFailed(e) => assert_eq!(e, "KeyError: 'data'"),
+            e => panic!("unexpected {:?}", e),
+        }
+        assert!(matches!(
+            classify_failure(stdout, ""),
+            FetchError::ProcessFailed(_)
+        ));
+    }
+
+    #[test]
+    fn account_state_formats() {
+        let renamed = AccountState::Renamed("nasa2".to_string());
+        assert_eq!(renamed.to_string(), "renamed@nasa2");
+        assert_eq!(renamed.description(), "renamed to @nasa2");
+        assert_eq!("renamed@nasa2".parse(), Ok(renamed));
+
+        assert_eq!(AccountState::NotFound.to_string(), "not_found");
+        assert_eq!(AccountState::NotFound.description(), "not found");
+        assert_eq!(AccountState::Suspended.description(), "suspended");
+    }
+
+    #[test]
+    fn workfile_removed_on_drop() {
+        let dir = utils::TempFile::new("scratch");
+        let dir = std::path::PathBuf::from(dir.path());
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let workfile = Workfile::new(&dir, "nasa");
+        let path = workfile.path.clone();
+        assert!(path.starts_with(&dir));
+        assert!(path.to_string_lossy().contains(WORKFILE_MARK));
+        std::fs::write(&path, "id\ttweet\n").unwrap();
+        drop(workfile);
+        assert!(!path.exists());
+
+        // twint didn't write anything
+        drop(Workfile::new(&dir, "nasa"));
+    }
+
+    #[test]
+    fn sweep_leftover_workfiles() {
+        let scratch = utils::TempFile::new("scratch");
+        let dir = std::path::Path::new(&scratch.path()).join("nested");
+
+        sweep_workfiles(&dir).unwrap();
+        assert!(dir.is_dir());
+
+        let leftover = Workfile::new(&dir, "nasa").path.clone();
+        std::fs::write(&leftover, "").unwrap();
+        for name in ["other.csv", "nasa_workfile.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        sweep_workfiles(&dir).unwrap();
+        assert!(!leftover.exists());
+        assert!(dir.join("other.csv").exists());
+        assert!(dir.join("nasa_workfile.txt").exists());
+
+        // Scratch directory can't be created where a file is
+        assert!(sweep_workfiles(&dir.join("other.csv")).is_err());
+    }
+
+    #[tokio::test]
+    async fn twint_timeout_kills_process_group() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Fake twint that never finishes and spawns a child of its own
+        let bin = utils::TempFile::new("bin");
+        let bin_dir = std::path::PathBuf::from(bin.path());
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        let script = bin_dir.join("twint");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\necho $$ > \"$1\"\nsleep 60 &\nsleep 60\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", bin_dir.display(), path));
+
+        let pid_file = utils::TempFile::new("twint_pid");
+        let options = ScraperOptions {
+            timeout: std::time::Duration::from_millis(500),
+            proxy: None,
+        };
+        let result = run_twint(&[&pid_file.path()], &options).await;
+        assert!(matches!(result, Err(FetchError::Timeout(_))));
+
+        let pgid = std::fs::read_to_string(pid_file.path())
+            .unwrap()
+            .trim()
+            .parse::<libc::pid_t>()
+            .unwrap();
+        // Killed processes may take a moment to be reaped
+        let mut alive = true;
+        for _ in 0..50 {
+            // SAFETY: signal 0 only checks whether the group exists
+            alive = unsafe { libc::killpg(pgid, 0) } == 0;
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!alive, "process group {} is still running", pgid);
+    }
+
+    #[test]
+    fn valid_usernames() {
+        assert!(is_valid_username("elonmusk"));
+        assert!(is_valid_username("a_1"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("sixteen_chars_xx"));
+        assert!(!is_valid_username("x' ; rm -rf ~"));
+        assert!(!is_valid_username("user.name"));
+    }
+
+    #[test]
+    fn parse_missing_column() {
+        let result = parse_twint_csv(
+            "id\tdate\ttime\ttimezone\ttweet\n1\t2022-08-20\t12:00:00\t+0200\thi\n",
+        );
+        assert!(matches!(result, Err(ParseError::MissingColumn("link"))));
+    }
+}
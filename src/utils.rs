@@ -1,110 +1,119 @@
-use log::warn;
+use bech32::{FromBase32, ToBase32};
 
-#[derive(Clone)]
-pub struct Config {
-    pub name: String,
-    pub about: String,
-    pub picture_url: String,
-    pub hello_message: String,
-    pub secret: String,
-    pub refresh_interval_secs: u64,
-    pub relays: Vec<String>,
-    pub max_follows: usize,
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
-impl std::fmt::Debug for Config {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        fmt.debug_struct("Config")
-            .field("name", &self.name)
-            .field("about", &self.about)
-            .field("picture_url", &self.picture_url)
-            .field("hello_message", &self.hello_message)
-            .field("secret", &"***")
-            .field("refresh_interval_secs", &self.refresh_interval_secs)
-            .field("relays", &self.relays)
-            .field("max_follows", &self.max_follows)
-            .finish()
-    }
+pub fn get_random_keypair() -> secp256k1::KeyPair {
+    let secp = secp256k1::Secp256k1::new();
+    let secret = secp256k1::SecretKey::new(&mut rand::thread_rng());
+    secret.keypair(&secp)
 }
 
-pub fn parse_config(path: &std::path::Path) -> Config {
-    let get_value = |line: String| {
-        let mut value = line.split('=').collect::<Vec<_>>()[1].to_string();
-        if value.starts_with('"') && value.ends_with('"') {
-            value = value[1..value.len() - 1].to_string();
-        }
-        value
-    };
+/// Returns bech32 encoded `pubkey`, see NIP-19.
+pub fn npub(pubkey: &secp256k1::XOnlyPublicKey) -> String {
+    bech32::encode(
+        "npub",
+        pubkey.serialize().to_base32(),
+        bech32::Variant::Bech32,
+    )
+    .unwrap()
+}
 
-    let content = std::fs::read_to_string(path).expect("Config reading failed.");
+/// Returns bech32 encoded `secret`, see NIP-19.
+pub fn nsec(secret: &secp256k1::SecretKey) -> String {
+    bech32::encode(
+        "nsec",
+        secret.secret_bytes().to_base32(),
+        bech32::Variant::Bech32,
+    )
+    .unwrap()
+}
 
-    let mut name = String::from("");
-    let mut about = String::from("");
-    let mut picture_url = String::from("");
-    let mut secret = String::new();
-    let mut hello_message = String::new();
-    let mut refresh_interval_secs = 0;
-    let mut relays = Vec::new();
-    let mut max_follows = 0;
+// Returns data of bech32 encoded `value` with human readable part `hrp`.
+fn decode_bech32(value: &str, hrp: &str) -> Result<Vec<u8>, String> {
+    let (decoded_hrp, data, _variant) =
+        bech32::decode(value).map_err(|e| format!("Invalid {}: {}", hrp, e))?;
+    if decoded_hrp != hrp {
+        return Err(format!("Invalid {} prefix {}", hrp, decoded_hrp));
+    }
+    Vec::<u8>::from_base32(&data).map_err(|e| format!("Invalid {}: {}", hrp, e))
+}
 
-    for line in content.lines() {
-        let line = line.to_string();
+/// Parses secret key given either as hex or as `nsec`.
+pub fn parse_secret(secret: &str) -> Result<secp256k1::SecretKey, String> {
+    if secret.starts_with("nsec1") {
+        secp256k1::SecretKey::from_slice(&decode_bech32(secret, "nsec")?)
+            .map_err(|e| format!("Invalid nsec: {}", e))
+    } else {
+        secret
+            .parse::<secp256k1::SecretKey>()
+            .map_err(|e| format!("Invalid secret key: {}", e))
+    }
+}
 
-        if line.starts_with("name") {
-            name = get_value(line);
-        } else if line.starts_with("about") {
-            about = get_value(line);
-        } else if line.starts_with("picture_url") {
-            picture_url = get_value(line);
-        } else if line.starts_with("hello_message") {
-            hello_message = get_value(line);
-        } else if line.starts_with("secret") {
-            secret = get_value(line);
-        } else if line.starts_with("refresh_interval_secs") {
-            refresh_interval_secs = get_value(line)
-                .parse::<u64>()
-                .expect("Failed to parse the refresh interval.");
-        } else if line.starts_with("addrelay") {
-            relays.push(get_value(line));
-        } else if line.starts_with("max_follows") {
-            max_follows = get_value(line).parse::<usize>().expect("Can't parse value");
-        } else if line.starts_with('#') || line.is_empty() {
-            // Ignoring comments and blank lines
-        } else {
-            warn!("Unknown config line >{}<", line);
-        }
+/// Parses public key given either as hex or as `npub`.
+pub fn parse_pubkey(pubkey: &str) -> Result<secp256k1::XOnlyPublicKey, String> {
+    if pubkey.starts_with("npub1") {
+        secp256k1::XOnlyPublicKey::from_slice(&decode_bech32(pubkey, "npub")?)
+            .map_err(|e| format!("Invalid npub: {}", e))
+    } else {
+        pubkey
+            .parse::<secp256k1::XOnlyPublicKey>()
+            .map_err(|e| format!("Invalid public key: {}", e))
     }
+}
 
-    assert!(!name.is_empty());
-    assert!(!about.is_empty());
-    assert!(!picture_url.is_empty());
-    assert!(!secret.is_empty());
-    assert!(!hello_message.is_empty());
-    assert!(refresh_interval_secs > 0);
-    assert!(!relays.is_empty());
-    assert!(max_follows > 0);
+/// File (or directory) in the temp directory for tests. It's removed when dropped, together with
+/// the `.tmp` file left by an interrupted save.
+#[cfg(test)]
+pub struct TempFile(String);
 
-    Config {
-        name,
-        about,
-        picture_url,
-        secret,
-        hello_message,
-        refresh_interval_secs,
-        relays,
-        max_follows,
+#[cfg(test)]
+impl TempFile {
+    pub fn new(name: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("tostr_{}_{}", name, rand::random::<u64>()));
+        TempFile(path.to_string_lossy().to_string())
+    }
+
+    pub fn path(&self) -> String {
+        self.0.clone()
     }
 }
 
-pub fn unix_timestamp() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+#[cfg(test)]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+        let _ = std::fs::remove_dir_all(&self.0);
+        let _ = std::fs::remove_file(format!("{}.tmp", self.0));
+    }
 }
 
-pub fn get_random_keypair() -> secp256k1::KeyPair {
-    let secp = secp256k1::Secp256k1::new();
-    let secret = secp256k1::SecretKey::new(&mut rand::thread_rng());
-    secret.keypair(&secp)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nsec_roundtrip() {
+        let keypair = get_random_keypair();
+        let secret = keypair.secret_key();
+
+        assert!(nsec(&secret).starts_with("nsec1"));
+        assert_eq!(parse_secret(&nsec(&secret)), Ok(secret));
+        assert_eq!(
+            parse_secret(&keypair.display_secret().to_string()),
+            Ok(secret)
+        );
+        assert!(parse_secret(&npub(&keypair.x_only_public_key().0)).is_err());
+        assert!(parse_secret("nsec1invalid").is_err());
+
+        let pubkey = keypair.x_only_public_key().0;
+        assert_eq!(parse_pubkey(&npub(&pubkey)), Ok(pubkey));
+        assert_eq!(parse_pubkey(&pubkey.to_string()), Ok(pubkey));
+        assert!(parse_pubkey(&nsec(&secret)).is_err());
+    }
 }